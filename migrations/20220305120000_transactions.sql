@@ -0,0 +1,14 @@
+-- ledger of every balance changing operation
+CREATE TABLE transactions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL,
+  user INTEGER,
+  product INTEGER,
+  amount INTEGER NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(user) REFERENCES user(id),
+  FOREIGN KEY(product) REFERENCES product(id)
+);
+
+CREATE INDEX transactions_user_idx ON transactions(user);
+CREATE INDEX transactions_created_at_idx ON transactions(created_at);
@@ -0,0 +1,5 @@
+ALTER TABLE product ADD COLUMN age_restricted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE user ADD COLUMN age_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- everything containing alcohol was sold to adults only anyway
+UPDATE product SET age_restricted = TRUE WHERE alcohol > 0;
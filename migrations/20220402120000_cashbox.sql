@@ -0,0 +1,8 @@
+-- counts and withdrawals of the physical cashbox
+CREATE TABLE cashbox (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  comment TEXT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,21 @@
+-- external reference of a booking, e.g. the remittance information of a bank
+-- transfer. Deposits with a reference did not go through the cashbox.
+ALTER TABLE transactions ADD COLUMN reference TEXT;
+
+-- lines of imported bank statements
+CREATE TABLE bank_transaction (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  -- unique identifier of the line assigned by the bank
+  bank_id TEXT NOT NULL UNIQUE,
+  booked_at DATE NOT NULL,
+  amount INTEGER NOT NULL,
+  reference TEXT NOT NULL,
+  name TEXT,
+  status TEXT NOT NULL,
+  user INTEGER,
+  "transaction" INTEGER,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(user) REFERENCES user(id),
+  FOREIGN KEY("transaction") REFERENCES transactions(id)
+);
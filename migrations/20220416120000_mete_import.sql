@@ -0,0 +1,20 @@
+-- barcodes of products and user cards
+CREATE TABLE barcode (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  code TEXT NOT NULL UNIQUE,
+  user INTEGER,
+  product INTEGER,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(user) REFERENCES user(id),
+  FOREIGN KEY(product) REFERENCES product(id)
+);
+
+-- records imported from mete and the local row they were mapped to
+CREATE TABLE mete_import (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL,
+  mete_id TEXT NOT NULL,
+  local_id INTEGER NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(kind, mete_id)
+);
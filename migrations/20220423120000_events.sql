@@ -0,0 +1,13 @@
+-- events like camps with their own temporary users
+CREATE TABLE event (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL UNIQUE,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  closed_at DATETIME
+);
+
+-- temporary users belong to an event
+ALTER TABLE user ADD COLUMN event INTEGER REFERENCES event(id);
+
+-- purchases made by users of an event
+ALTER TABLE transactions ADD COLUMN event INTEGER REFERENCES event(id);
@@ -0,0 +1,23 @@
+-- shared tabs of several users
+CREATE TABLE group_account (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL UNIQUE,
+  balance INTEGER NOT NULL DEFAULT 0,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- users allowed to charge a group account, optionally up to a cap
+CREATE TABLE group_member (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  group_account INTEGER NOT NULL,
+  user INTEGER NOT NULL,
+  cap INTEGER,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(group_account, user),
+  FOREIGN KEY(group_account) REFERENCES group_account(id),
+  FOREIGN KEY(user) REFERENCES user(id)
+);
+
+-- ledger entries changing the balance of a group account instead of the user
+ALTER TABLE transactions ADD COLUMN group_account INTEGER REFERENCES group_account(id);
@@ -0,0 +1,32 @@
+-- charges booked periodically, e.g. membership fees
+CREATE TABLE recurring_charge (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  interval TEXT NOT NULL,
+  starts_on DATE NOT NULL,
+  group_account INTEGER,
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(group_account) REFERENCES group_account(id)
+);
+
+-- users a recurring charge is booked for
+CREATE TABLE recurring_charge_user (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  charge INTEGER NOT NULL,
+  user INTEGER NOT NULL,
+  UNIQUE(charge, user),
+  FOREIGN KEY(charge) REFERENCES recurring_charge(id),
+  FOREIGN KEY(user) REFERENCES user(id)
+);
+
+-- every period of a recurring charge is booked exactly once
+CREATE TABLE recurring_booking (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  charge INTEGER NOT NULL,
+  period DATE NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(charge, period),
+  FOREIGN KEY(charge) REFERENCES recurring_charge(id)
+);
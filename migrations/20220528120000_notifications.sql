@@ -0,0 +1,11 @@
+-- users may opt out of all email notifications
+ALTER TABLE user ADD COLUMN notifications BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- every email sent to a user, used to not send a reminder twice
+CREATE TABLE notification (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user INTEGER NOT NULL,
+  kind TEXT NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(user) REFERENCES user(id)
+);
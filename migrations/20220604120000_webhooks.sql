@@ -0,0 +1,21 @@
+-- events for webhooks, written in the same transaction as the change itself
+CREATE TABLE webhook_event (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  dispatched BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- an event to be posted to a single subscriber, retried until it succeeds
+CREATE TABLE webhook_delivery (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  event INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at DATETIME,
+  last_error TEXT,
+  FOREIGN KEY(event) REFERENCES webhook_event(id)
+);
+CREATE INDEX webhook_delivery_pending ON webhook_delivery(delivered_at, next_attempt_at);
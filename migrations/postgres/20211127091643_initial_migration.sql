@@ -0,0 +1,34 @@
+-- same schema as the sqlite migrations, timestamps are stored in utc
+CREATE TABLE image (
+  id SERIAL PRIMARY KEY,
+  file_name TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE product (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  caffeine INTEGER,
+  alcohol INTEGER,
+  energy INTEGER,
+  sugar INTEGER,
+  price INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  image INTEGER REFERENCES image(id)
+);
+
+CREATE TABLE "user" (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  email TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  balance INTEGER NOT NULL DEFAULT 0,
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  audit BOOLEAN NOT NULL DEFAULT FALSE,
+  redirect BOOLEAN NOT NULL DEFAULT TRUE,
+  avatar INTEGER REFERENCES image(id)
+);
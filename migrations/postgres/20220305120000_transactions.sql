@@ -0,0 +1,12 @@
+-- ledger of every balance changing operation
+CREATE TABLE transactions (
+  id SERIAL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  "user" INTEGER REFERENCES "user"(id),
+  product INTEGER REFERENCES product(id),
+  amount INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE INDEX transactions_user_idx ON transactions("user");
+CREATE INDEX transactions_created_at_idx ON transactions(created_at);
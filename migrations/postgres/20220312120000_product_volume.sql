@@ -0,0 +1,2 @@
+-- package size of a product in ml or g
+ALTER TABLE product ADD COLUMN volume INTEGER;
@@ -0,0 +1,2 @@
+-- number of items in stock, NULL if the stock is not tracked
+ALTER TABLE product ADD COLUMN stock INTEGER;
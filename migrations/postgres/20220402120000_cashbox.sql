@@ -0,0 +1,8 @@
+-- counts and withdrawals of the physical cashbox
+CREATE TABLE cashbox (
+  id SERIAL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  comment TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
@@ -0,0 +1,19 @@
+-- external reference of a booking, e.g. the remittance information of a bank
+-- transfer. Deposits with a reference did not go through the cashbox.
+ALTER TABLE transactions ADD COLUMN reference TEXT;
+
+-- lines of imported bank statements
+CREATE TABLE bank_transaction (
+  id SERIAL PRIMARY KEY,
+  -- unique identifier of the line assigned by the bank
+  bank_id TEXT NOT NULL UNIQUE,
+  booked_at DATE NOT NULL,
+  amount INTEGER NOT NULL,
+  reference TEXT NOT NULL,
+  name TEXT,
+  status TEXT NOT NULL,
+  "user" INTEGER REFERENCES "user"(id),
+  "transaction" INTEGER REFERENCES transactions(id),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
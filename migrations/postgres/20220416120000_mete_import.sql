@@ -0,0 +1,18 @@
+-- barcodes of products and user cards
+CREATE TABLE barcode (
+  id SERIAL PRIMARY KEY,
+  code TEXT NOT NULL UNIQUE,
+  "user" INTEGER REFERENCES "user"(id),
+  product INTEGER REFERENCES product(id),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+-- records imported from mete and the local row they were mapped to
+CREATE TABLE mete_import (
+  id SERIAL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  mete_id TEXT NOT NULL,
+  local_id INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  UNIQUE(kind, mete_id)
+);
@@ -0,0 +1,13 @@
+-- events like camps with their own temporary users
+CREATE TABLE event (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  closed_at TIMESTAMP
+);
+
+-- temporary users belong to an event
+ALTER TABLE "user" ADD COLUMN event INTEGER REFERENCES event(id);
+
+-- purchases made by users of an event
+ALTER TABLE transactions ADD COLUMN event INTEGER REFERENCES event(id);
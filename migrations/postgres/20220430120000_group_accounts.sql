@@ -0,0 +1,21 @@
+-- shared tabs of several users
+CREATE TABLE group_account (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  balance INTEGER NOT NULL DEFAULT 0,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+-- users allowed to charge a group account, optionally up to a cap
+CREATE TABLE group_member (
+  id SERIAL PRIMARY KEY,
+  group_account INTEGER NOT NULL REFERENCES group_account(id),
+  "user" INTEGER NOT NULL REFERENCES "user"(id),
+  cap INTEGER,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  UNIQUE(group_account, "user")
+);
+
+-- ledger entries changing the balance of a group account instead of the user
+ALTER TABLE transactions ADD COLUMN group_account INTEGER REFERENCES group_account(id);
@@ -0,0 +1,2 @@
+-- users merged into another one are archived and point to the remaining user
+ALTER TABLE "user" ADD COLUMN merged_into INTEGER REFERENCES "user"(id);
@@ -0,0 +1,28 @@
+-- charges booked periodically, e.g. membership fees
+CREATE TABLE recurring_charge (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  "interval" TEXT NOT NULL,
+  starts_on DATE NOT NULL,
+  group_account INTEGER REFERENCES group_account(id),
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+-- users a recurring charge is booked for
+CREATE TABLE recurring_charge_user (
+  id SERIAL PRIMARY KEY,
+  charge INTEGER NOT NULL REFERENCES recurring_charge(id),
+  "user" INTEGER NOT NULL REFERENCES "user"(id),
+  UNIQUE(charge, "user")
+);
+
+-- every period of a recurring charge is booked exactly once
+CREATE TABLE recurring_booking (
+  id SERIAL PRIMARY KEY,
+  charge INTEGER NOT NULL REFERENCES recurring_charge(id),
+  period DATE NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  UNIQUE(charge, period)
+);
@@ -0,0 +1,2 @@
+-- archived users are kept for the history only
+ALTER TABLE "user" ADD COLUMN archived_at TIMESTAMP;
@@ -0,0 +1,10 @@
+-- users may opt out of all email notifications
+ALTER TABLE "user" ADD COLUMN notifications BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- every email sent to a user, used to not send a reminder twice
+CREATE TABLE notification (
+  id SERIAL PRIMARY KEY,
+  "user" INTEGER NOT NULL REFERENCES "user"(id),
+  kind TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
@@ -0,0 +1,20 @@
+-- events for webhooks, written in the same transaction as the change itself
+CREATE TABLE webhook_event (
+  id SERIAL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  dispatched BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+-- an event to be posted to a single subscriber, retried until it succeeds
+CREATE TABLE webhook_delivery (
+  id SERIAL PRIMARY KEY,
+  event INTEGER NOT NULL REFERENCES webhook_event(id),
+  url TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  delivered_at TIMESTAMP,
+  last_error TEXT
+);
+CREATE INDEX webhook_delivery_pending ON webhook_delivery(delivered_at, next_attempt_at);
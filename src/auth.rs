@@ -0,0 +1,64 @@
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequest, RequestParts},
+    http::header::AUTHORIZATION,
+};
+
+use hmac::digest::CtOutput;
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, utils::AppError};
+
+/// Extractor that only succeeds if the request carries the configured admin
+/// token as `Authorization: Bearer <token>`. If no token is configured all
+/// admin requests are rejected.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Admin;
+
+/// Compares the digests of both in constant time, so the response time doesn't
+/// tell how much of a guessed token was right.
+fn token_matches(given: &str, token: &str) -> bool {
+    let digest = |value: &str| CtOutput::<Sha256>::new(Sha256::digest(value.as_bytes()));
+    digest(given) == digest(token)
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(config) = Extension::<Config>::from_request(req)
+            .await
+            .map_err(|_| AppError::Unauthorized)?;
+        let token = config.admin.token.ok_or(AppError::Unauthorized)?;
+
+        let header = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AppError::Unauthorized)?;
+
+        if token_matches(header, &token) {
+            Ok(Admin)
+        } else {
+            Err(AppError::Unauthorized)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secre", "secret"));
+        assert!(!token_matches("secret ", "secret"));
+        assert!(!token_matches("", "secret"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use axum::{extract::Extension, routing, Json, Router};
+use chrono::Local;
+use eyre::{eyre, Context};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode},
+    ConnectOptions, Connection,
+};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Admin,
+    config::{BackupConfig, Config},
+    models::BackupInfo,
+    storage::{self, Db},
+    utils::{AppError, Result},
+};
+
+const PREFIX: &str = "database-";
+const SUFFIX: &str = ".sqlite";
+
+pub fn router() -> Router {
+    Router::new().route("/", routing::post(create))
+}
+
+/// path of the database file in a connection string like `sqlite://database.sqlite`
+fn database_path(conn_str: &str) -> Option<PathBuf> {
+    let path = conn_str
+        .strip_prefix("sqlite:")?
+        .trim_start_matches("//")
+        .split('?')
+        .next()?;
+    if path.is_empty() || path == ":memory:" {
+        return None;
+    }
+    Some(PathBuf::from(path))
+}
+
+/// backups to delete so only the newest `keep` remain, names sort by their date
+fn expired(mut names: Vec<String>, keep: usize) -> Vec<String> {
+    names.retain(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX));
+    names.sort();
+    let expired = names.len().saturating_sub(keep);
+    names.truncate(expired);
+    names
+}
+
+/// fails unless sqlite considers the database at `path` intact
+async fn check_integrity(path: &Path) -> eyre::Result<()> {
+    let mut conn: SqliteConnection = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        // switching to the default wal mode would be a write
+        .journal_mode(SqliteJournalMode::Delete)
+        .connect()
+        .await
+        .wrap_err_with(|| eyre!("unable to open {}", path.display()))?;
+    let result: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&mut conn)
+        .await
+        .wrap_err_with(|| eyre!("unable to check {}", path.display()))?;
+    conn.close().await?;
+
+    if result != "ok" {
+        eyre::bail!("{} is damaged: {}", path.display(), result);
+    }
+    Ok(())
+}
+
+/// Writes a consistent copy of the running database to `path` and verifies
+/// it. Other connections can keep on writing while the copy is made.
+async fn write_backup(db: &Db, path: &Path) -> eyre::Result<BackupInfo> {
+    let pool = db
+        .pool
+        .sqlite()
+        .ok_or_else(|| eyre!("only sqlite databases can be backed up, use pg_dump for postgres"))?;
+    let file = path
+        .to_str()
+        .ok_or_else(|| eyre!("invalid backup path {}", path.display()))?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(file)
+        .execute(pool)
+        .await
+        .wrap_err_with(|| eyre!("unable to write backup to {}", path.display()))?;
+    check_integrity(path).await?;
+
+    Ok(BackupInfo {
+        file: file.to_owned(),
+        size: tokio::fs::metadata(path).await?.len(),
+    })
+}
+
+/// deletes all but the newest `keep` backups in the directory
+async fn prune(directory: &Path, keep: usize) -> eyre::Result<()> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        names.extend(entry.file_name().into_string());
+    }
+    for name in expired(names, keep) {
+        tokio::fs::remove_file(directory.join(&name))
+            .await
+            .wrap_err_with(|| eyre!("unable to delete old backup {}", name))?;
+    }
+    Ok(())
+}
+
+/// a new backup in the configured directory, old ones are pruned afterwards
+async fn backup(db: &Db, config: &BackupConfig) -> eyre::Result<BackupInfo> {
+    let directory = Path::new(&config.directory);
+    tokio::fs::create_dir_all(directory)
+        .await
+        .wrap_err_with(|| eyre!("unable to create {}", directory.display()))?;
+    let name = format!(
+        "{}{}{}",
+        PREFIX,
+        Local::now().format("%Y%m%d-%H%M%S%.3f"),
+        SUFFIX
+    );
+    let info = write_backup(db, &directory.join(name)).await?;
+    prune(directory, config.keep).await?;
+    Ok(info)
+}
+
+/// backs up the database right away
+async fn create(
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<Config>,
+    _: Admin,
+) -> Result<Json<BackupInfo>> {
+    if db.pool.sqlite().is_none() {
+        return Err(AppError::InvalidInput(
+            "only sqlite databases can be backed up".to_string(),
+        ));
+    }
+    Ok(Json(backup(&db, &config.backup).await?))
+}
+
+/// background task backing up the database, does nothing if no interval is
+/// configured
+pub async fn scheduler(db: Db, config: BackupConfig) {
+    let hours = match config.interval_hours {
+        Some(hours) if hours > 0 => hours,
+        _ => return,
+    };
+    if db.pool.sqlite().is_none() {
+        warn!("scheduled backups are only supported for sqlite databases");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(u64::from(hours) * 60 * 60));
+    loop {
+        interval.tick().await;
+        match backup(&db, &config).await {
+            Ok(info) => info!("backed up database to {}", info.file),
+            Err(err) => warn!("unable to back up database: {:?}", err),
+        }
+    }
+}
+
+/// backs up the database into `output` or the configured directory
+pub async fn run(db: &Db, config: &BackupConfig, output: Option<PathBuf>) -> eyre::Result<()> {
+    let info = match output {
+        Some(path) => write_backup(db, &path).await?,
+        None => backup(db, config).await?,
+    };
+    println!("wrote {} ({} bytes)", info.file, info.size);
+    Ok(())
+}
+
+/// Replaces the database by a backup after checking its integrity. The
+/// current database is backed up first. Must not run while the server is.
+pub async fn restore(config: &Config, source: PathBuf) -> eyre::Result<()> {
+    let target = database_path(&config.storage.database)
+        .ok_or_else(|| eyre!("only sqlite databases stored in a file can be restored"))?;
+    check_integrity(&source).await?;
+
+    // copied next to the database first, the swap itself can't fail halfway
+    // and pruning can't delete the source in the meantime
+    let mut copy = target.clone().into_os_string();
+    copy.push(".restore");
+    tokio::fs::copy(&source, &copy)
+        .await
+        .wrap_err_with(|| eyre!("unable to copy {}", source.display()))?;
+
+    if tokio::fs::metadata(&target).await.is_ok() {
+        let db = storage::open_db(&config.storage).await?;
+        let result = backup(&db, &config.backup).await;
+        db.pool.close().await;
+        match result {
+            Ok(info) => println!("backed up current database to {}", info.file),
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&copy).await;
+                return Err(err.wrap_err("unable to back up the current database"));
+            }
+        }
+    }
+
+    for suffix in ["-wal", "-shm"] {
+        let mut journal = target.clone().into_os_string();
+        journal.push(suffix);
+        if let Err(err) = tokio::fs::remove_file(&journal).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+    }
+    tokio::fs::rename(&copy, &target).await?;
+
+    println!("restored {} from {}", target.display(), source.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn database_paths_and_retention() {
+        assert_eq!(
+            database_path("sqlite://database.sqlite"),
+            Some(PathBuf::from("database.sqlite"))
+        );
+        assert_eq!(
+            database_path("sqlite:///var/lib/mate/kasse.sqlite?mode=rwc"),
+            Some(PathBuf::from("/var/lib/mate/kasse.sqlite"))
+        );
+        assert_eq!(database_path("sqlite::memory:"), None);
+        assert_eq!(database_path("postgres://localhost/matekasse"), None);
+
+        let names = [
+            "database-20220612-120000.000.sqlite",
+            "notes.txt",
+            "database-20220610-120000.500.sqlite",
+            "database-20220611-120000.000.sqlite",
+        ];
+        assert_eq!(
+            expired(names.map(String::from).to_vec(), 2),
+            vec!["database-20220610-120000.500.sqlite"]
+        );
+        assert_eq!(
+            expired(names.map(String::from).to_vec(), 5),
+            Vec::<String>::new()
+        );
+    }
+}
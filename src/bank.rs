@@ -0,0 +1,217 @@
+use axum::{
+    extract::{Extension, Path, Query},
+    routing, Json, Router,
+};
+use serde::Deserialize;
+
+use sea_orm::{entity::*, query::*, TransactionTrait};
+
+use crate::{
+    auth::Admin,
+    config::Config,
+    entity::{
+        bank_transaction::{self, Status},
+        transaction::{self, Kind},
+        user, webhook_event,
+    },
+    live::Live,
+    models::{
+        BankAcceptRequest, BankImportQuery, BankImportResponse, BankTransaction, LedgerEntry,
+        LiveEvent, StatementFormat,
+    },
+    storage::Db,
+    user::ensure_bookable,
+    utils::{AppError, Result},
+    webhooks,
+};
+
+mod matching;
+mod statement;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", routing::get(get_all))
+        .route("/import", routing::post(import))
+        .route("/:id/accept", routing::post(accept))
+        .route("/:id/reject", routing::post(reject))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ListQuery {
+    status: Option<Status>,
+}
+
+/// imported bank transactions, optionally filtered by status
+async fn get_all(
+    Query(query): Query<ListQuery>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<Json<Vec<BankTransaction>>> {
+    let mut select = bank_transaction::Entity::find();
+    if let Some(status) = query.status {
+        select = select.filter(bank_transaction::Column::Status.eq(status));
+    }
+    let transactions = select
+        .order_by_asc(bank_transaction::Column::BookedAt)
+        .all(&db.orm)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(transactions))
+}
+
+/// imports the incoming payments of a bank statement and proposes a user for
+/// each of them, lines that were imported before are skipped
+async fn import(
+    Query(query): Query<BankImportQuery>,
+    body: String,
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<Config>,
+    _: Admin,
+) -> Result<Json<BankImportResponse>> {
+    let lines = match query.format {
+        StatementFormat::Camt => statement::parse_camt(&body),
+        StatementFormat::Csv => statement::parse_csv(&body, &config.bank.csv),
+    }
+    .map_err(AppError::InvalidInput)?;
+
+    let response = db
+        .orm
+        .transaction::<_, BankImportResponse, AppError>(|txn| {
+            Box::pin(async move {
+                let users = user::Entity::find()
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|u| (u.id, u.name))
+                    .collect::<Vec<_>>();
+
+                let mut response = BankImportResponse::default();
+                for line in lines {
+                    if line.amount <= 0 {
+                        response.skipped += 1;
+                        continue;
+                    }
+                    let existing = bank_transaction::Entity::find()
+                        .filter(bank_transaction::Column::BankId.eq(line.bank_id.clone()))
+                        .one(txn)
+                        .await?;
+                    if existing.is_some() {
+                        response.duplicates += 1;
+                        continue;
+                    }
+
+                    let user =
+                        matching::propose_user(&line.reference, line.name.as_deref(), &users);
+                    let imported = bank_transaction::ActiveModel {
+                        bank_id: Set(line.bank_id),
+                        booked_at: Set(line.booked_at),
+                        amount: Set(line.amount),
+                        reference: Set(line.reference),
+                        name: Set(line.name),
+                        status: Set(Status::Pending),
+                        user: Set(user),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+                    response.imported.push(imported.into());
+                }
+
+                Ok(response)
+            })
+        })
+        .await?;
+
+    Ok(Json(response))
+}
+
+async fn pending(txn: &impl ConnectionTrait, id: i32) -> Result<bank_transaction::Model> {
+    let bank_transaction = bank_transaction::Entity::find_by_id(id)
+        .one(txn)
+        .await?
+        .ok_or(AppError::NotFount)?;
+    if bank_transaction.status != Status::Pending {
+        return Err(AppError::Conflict);
+    }
+    Ok(bank_transaction)
+}
+
+/// books a pending bank transaction as deposit for the proposed or given user
+async fn accept(
+    Path(id): Path<i32>,
+    request: Option<Json<BankAcceptRequest>>,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    _: Admin,
+) -> Result<Json<BankTransaction>> {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let (bank_transaction, user, deposit) = db
+        .orm
+        .transaction::<_, _, AppError>(|txn| {
+            Box::pin(async move {
+                let bank_transaction = pending(txn, id).await?;
+                let user_id = request
+                    .user
+                    .or(bank_transaction.user)
+                    .ok_or_else(|| AppError::InvalidInput("no user given".to_string()))?;
+                let user = user::Entity::find_by_id(user_id)
+                    .one(txn)
+                    .await?
+                    .ok_or(AppError::NotFount)?;
+                ensure_bookable(&user)?;
+
+                let balance = user.balance;
+                let mut user = user.into_active_model();
+                user.balance = Set(balance + bank_transaction.amount);
+                let user = user.update(txn).await?;
+
+                let deposit = transaction::ActiveModel {
+                    kind: Set(Kind::Deposit),
+                    user: Set(Some(user_id)),
+                    amount: Set(bank_transaction.amount),
+                    reference: Set(Some(bank_transaction.reference.clone())),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+                let data = LedgerEntry::from(deposit.clone());
+                webhooks::emit(txn, webhook_event::Kind::Deposit, &data).await?;
+
+                let mut bank_transaction = bank_transaction.into_active_model();
+                bank_transaction.status = Set(Status::Accepted);
+                bank_transaction.user = Set(Some(user_id));
+                bank_transaction.transaction = Set(Some(deposit.id));
+                Ok((bank_transaction.update(txn).await?, user, deposit))
+            })
+        })
+        .await?;
+
+    live.publish([
+        LiveEvent::User(user.into()),
+        LiveEvent::Transaction(deposit.into()),
+    ]);
+    Ok(Json(bank_transaction.into()))
+}
+
+/// marks a pending bank transaction as not belonging to the kasse
+async fn reject(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<Json<BankTransaction>> {
+    let bank_transaction = db
+        .orm
+        .transaction::<_, bank_transaction::Model, AppError>(|txn| {
+            Box::pin(async move {
+                let mut bank_transaction = pending(txn, id).await?.into_active_model();
+                bank_transaction.status = Set(Status::Rejected);
+                Ok(bank_transaction.update(txn).await?)
+            })
+        })
+        .await?;
+
+    Ok(Json(bank_transaction.into()))
+}
@@ -0,0 +1,110 @@
+/// minimal similarity between a word of the reference and a user name
+const MIN_SIMILARITY: f64 = 0.8;
+
+/// names shorter than this have to match exactly
+const MIN_FUZZY_LENGTH: usize = 4;
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || "#-_.".contains(c)))
+        .map(|w| w.trim_matches('.').to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// an explicit user id in the reference, e.g. `#12` or `ID: 12`
+fn explicit_id(words: &[String]) -> Option<i32> {
+    words.iter().enumerate().find_map(|(i, word)| {
+        if let Some(id) = word.strip_prefix('#') {
+            return id.parse().ok();
+        }
+        match word.strip_prefix("id") {
+            Some("") => words.get(i + 1).and_then(|next| next.parse().ok()),
+            Some(id) => id.parse().ok(),
+            None => None,
+        }
+    })
+}
+
+fn similarity(word: &str, name: &str) -> f64 {
+    if word == name {
+        1.0
+    } else if name.chars().count() < MIN_FUZZY_LENGTH {
+        0.0
+    } else {
+        strsim::normalized_levenshtein(word, name)
+    }
+}
+
+/// Proposes the user a bank transfer belongs to. An explicit id in the
+/// reference wins, otherwise the user whose name is most similar to a word of
+/// the reference or the name of the payer is chosen. Returns `None` if there
+/// is no or no unambiguous match.
+pub(crate) fn propose_user(
+    reference: &str,
+    payer: Option<&str>,
+    users: &[(i32, String)],
+) -> Option<i32> {
+    let words = words(reference);
+    if let Some(id) = explicit_id(&words).filter(|id| users.iter().any(|(u, _)| u == id)) {
+        return Some(id);
+    }
+
+    let payer = payer.map(str::to_lowercase);
+    let mut scores = users
+        .iter()
+        .map(|(id, name)| {
+            let name = name.to_lowercase();
+            let score = words
+                .iter()
+                .map(String::as_str)
+                .chain(payer.as_deref())
+                .map(|word| similarity(word, &name))
+                .fold(0.0, f64::max);
+            (*id, score)
+        })
+        .filter(|(_, score)| *score >= MIN_SIMILARITY)
+        .collect::<Vec<_>>();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    match scores.as_slice() {
+        [(id, _)] => Some(*id),
+        [(id, best), (_, second), ..] if best > second => Some(*id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn users() -> Vec<(i32, String)> {
+        vec![
+            (1, "alice".to_string()),
+            (2, "Alice2".to_string()),
+            (3, "bob".to_string()),
+            (4, "mallory".to_string()),
+        ]
+    }
+
+    #[test]
+    fn explicit_id_wins() {
+        assert_eq!(
+            propose_user("Matekasse ID: 3 alice", None, &users()),
+            Some(3)
+        );
+        assert_eq!(propose_user("matekasse #4", None, &users()), Some(4));
+        assert_eq!(propose_user("matekasse #42", None, &users()), None);
+    }
+
+    #[test]
+    fn fuzzy_names() {
+        assert_eq!(propose_user("Matekasse alice", None, &users()), Some(1));
+        assert_eq!(propose_user("mate Malory", None, &users()), Some(4));
+        assert_eq!(propose_user("Aufladung", Some("Bob"), &users()), Some(3));
+        // short names have to match exactly
+        assert_eq!(propose_user("bop", None, &users()), None);
+        assert_eq!(propose_user("thanks", None, &users()), None);
+    }
+}
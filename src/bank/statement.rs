@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use roxmltree::{Document, Node};
+
+use crate::config::BankCsvConfig;
+
+/// a single line of a bank statement
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BankLine {
+    /// unique identifier of the line
+    pub bank_id: String,
+    pub booked_at: NaiveDate,
+    /// amount in cent, negative for outgoing payments
+    pub amount: i32,
+    /// remittance information
+    pub reference: String,
+    /// name of the other party
+    pub name: Option<String>,
+}
+
+/// parses a decimal amount like `-1.234,50` into cent
+pub(crate) fn parse_amount(value: &str, decimal_separator: char) -> Result<i32, String> {
+    let invalid = || format!("invalid amount: {:?}", value);
+
+    let value = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\'')
+        .filter(|c| c.is_ascii_digit() || *c == '-' || *c == '+' || *c == decimal_separator)
+        .collect::<String>();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let (units, cents) = match value.split_once(decimal_separator) {
+        Some((units, cents)) => (units, cents),
+        None => (value, ""),
+    };
+    if units.is_empty() && cents.is_empty() || cents.len() > 2 {
+        return Err(invalid());
+    }
+
+    let units = if units.is_empty() {
+        0
+    } else {
+        units.parse::<i32>().map_err(|_| invalid())?
+    };
+    let cents = match cents.len() {
+        0 => 0,
+        1 => cents.parse::<i32>().map_err(|_| invalid())? * 10,
+        _ => cents.parse::<i32>().map_err(|_| invalid())?,
+    };
+    let amount = units
+        .checked_mul(100)
+        .and_then(|units| units.checked_add(cents))
+        .ok_or_else(invalid)?;
+
+    Ok(if negative { -amount } else { amount })
+}
+
+/// Banks do not always provide a unique id for every line. In that case the
+/// id is derived from the contents and the occurrence of identical lines
+/// within the same statement, so importing the statement again yields the
+/// same ids.
+fn assign_fallback_ids(lines: &mut [BankLine]) {
+    let mut seen = HashMap::new();
+    for line in lines.iter_mut().filter(|line| line.bank_id.is_empty()) {
+        let id = format!(
+            "{}|{}|{}|{}",
+            line.booked_at,
+            line.amount,
+            line.name.as_deref().unwrap_or_default(),
+            line.reference
+        );
+        let occurrence = seen.entry(id.clone()).or_insert(0);
+        *occurrence += 1;
+        line.bank_id = if *occurrence == 1 {
+            id
+        } else {
+            format!("{}|{}", id, occurrence)
+        };
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| c.tag_name().name() == name)
+}
+
+fn path<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| child(node, name))
+}
+
+fn text(node: Option<Node>) -> Option<String> {
+    node.and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|t| !t.is_empty() && *t != "NOTPROVIDED")
+        .map(ToOwned::to_owned)
+}
+
+/// parses the entries of a CAMT.053 bank to customer statement
+pub(crate) fn parse_camt(xml: &str) -> Result<Vec<BankLine>, String> {
+    let document = Document::parse(xml).map_err(|e| format!("invalid xml: {}", e))?;
+
+    let mut lines = document
+        .descendants()
+        .filter(|n| n.tag_name().name() == "Ntry")
+        .map(|entry| {
+            let amount = text(child(entry, "Amt")).ok_or("entry without amount")?;
+            let amount = parse_amount(&amount, '.')?;
+            let amount = match text(child(entry, "CdtDbtInd")).as_deref() {
+                Some("DBIT") => -amount,
+                _ => amount,
+            };
+
+            let date = text(path(entry, &["BookgDt", "Dt"]))
+                .or_else(|| text(path(entry, &["BookgDt", "DtTm"])))
+                .or_else(|| text(path(entry, &["ValDt", "Dt"])))
+                .ok_or("entry without booking date")?;
+            let booked_at = NaiveDate::parse_from_str(date.get(..10).unwrap_or(&date), "%Y-%m-%d")
+                .map_err(|e| format!("invalid booking date {:?}: {}", date, e))?;
+
+            let details = path(entry, &["NtryDtls", "TxDtls"]);
+            let bank_id = text(child(entry, "AcctSvcrRef"))
+                .or_else(|| details.and_then(|d| text(path(d, &["Refs", "AcctSvcrRef"]))))
+                .or_else(|| details.and_then(|d| text(path(d, &["Refs", "EndToEndId"]))))
+                .unwrap_or_default();
+
+            let reference = details
+                .and_then(|d| child(d, "RmtInf"))
+                .map(|r| {
+                    r.children()
+                        .filter(|c| c.tag_name().name() == "Ustrd")
+                        .filter_map(|c| text(Some(c)))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+
+            // the payer is the debtor for incoming and the creditor for outgoing payments
+            let party = if amount < 0 { "Cdtr" } else { "Dbtr" };
+            let name = details
+                .and_then(|d| path(d, &["RltdPties", party]))
+                .and_then(|p| p.descendants().find(|n| n.tag_name().name() == "Nm"))
+                .and_then(|n| text(Some(n)));
+
+            Ok(BankLine {
+                bank_id,
+                booked_at,
+                amount,
+                reference,
+                name,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    assign_fallback_ids(&mut lines);
+    Ok(lines)
+}
+
+/// parses a csv export with the layout described by the config
+pub(crate) fn parse_csv(data: &str, config: &BankCsvConfig) -> Result<Vec<BankLine>, String> {
+    let delimiter = u8::try_from(config.delimiter).map_err(|_| "delimiter is not ascii")?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(data.as_bytes());
+
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim() == name)
+            .ok_or_else(|| format!("missing column: {}", name))
+    };
+    let date = column(&config.date)?;
+    let amount = column(&config.amount)?;
+    let reference = column(&config.reference)?;
+    let name = column(&config.name).ok();
+    let id = config.id.as_deref().map(column).transpose()?;
+
+    let mut lines = reader
+        .records()
+        .enumerate()
+        .map(|(row, record)| {
+            let record = record.map_err(|e| e.to_string())?;
+            let field = |column: usize| record.get(column).unwrap_or_default().trim();
+            let error = |e: String| format!("line {}: {}", row + 2, e);
+
+            Ok(BankLine {
+                bank_id: id.map(field).unwrap_or_default().to_owned(),
+                booked_at: NaiveDate::parse_from_str(field(date), &config.date_format)
+                    .map_err(|e| error(format!("invalid date {:?}: {}", field(date), e)))?,
+                amount: parse_amount(field(amount), config.decimal_separator).map_err(error)?,
+                reference: field(reference).to_owned(),
+                name: name
+                    .map(field)
+                    .filter(|n| !n.is_empty())
+                    .map(ToOwned::to_owned),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    assign_fallback_ids(&mut lines);
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const CAMT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Ntry>
+        <Amt Ccy="EUR">20.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <BookgDt><Dt>2022-04-01</Dt></BookgDt>
+        <AcctSvcrRef>2022040100001</AcctSvcrRef>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties><Dbtr><Nm>Alice Example</Nm></Dbtr></RltdPties>
+            <RmtInf><Ustrd>Matekasse alice</Ustrd></RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">45.5</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <BookgDt><Dt>2022-04-02</Dt></BookgDt>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_amount("12.50", '.'), Ok(1250));
+        assert_eq!(parse_amount("-1.234,5", ','), Ok(-123450));
+        assert_eq!(parse_amount("+3", ','), Ok(300));
+        assert_eq!(parse_amount(",99", ','), Ok(99));
+        assert!(parse_amount("1.234", '.').is_err());
+        assert!(parse_amount("", '.').is_err());
+    }
+
+    #[test]
+    fn camt_entries() {
+        let lines = parse_camt(CAMT).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                BankLine {
+                    bank_id: "2022040100001".to_string(),
+                    booked_at: NaiveDate::from_ymd(2022, 4, 1),
+                    amount: 2000,
+                    reference: "Matekasse alice".to_string(),
+                    name: Some("Alice Example".to_string()),
+                },
+                BankLine {
+                    bank_id: "2022-04-02|-4550||".to_string(),
+                    booked_at: NaiveDate::from_ymd(2022, 4, 2),
+                    amount: -4550,
+                    reference: String::new(),
+                    name: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_lines_with_identical_content_get_distinct_ids() {
+        let config = BankCsvConfig {
+            delimiter: ';',
+            date_format: "%d.%m.%Y".to_string(),
+            decimal_separator: ',',
+            ..Default::default()
+        };
+        let data = "date;amount;reference;name\n\
+                    01.04.2022;10,00;bob;Bob\n\
+                    01.04.2022;10,00;bob;Bob\n";
+
+        let ids = parse_csv(data, &config)
+            .unwrap()
+            .into_iter()
+            .map(|l| l.bank_id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ids,
+            vec!["2022-04-01|1000|Bob|bob", "2022-04-01|1000|Bob|bob|2"]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use axum::{
+    extract::{Extension, Query},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use chrono::NaiveDateTime;
+use sea_orm::{entity::*, query::*, ConnectionTrait};
+
+use crate::{
+    auth::Admin,
+    entity::{
+        cashbox::{self, Kind as CashboxKind},
+        transaction::{self, Kind},
+    },
+    models::{CashboxEntry, CashboxRequest, CashboxStatus, Reconciliation, StatsQuery},
+    storage::Db,
+    utils::Result,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", routing::get(status))
+        .route("/count", routing::post(count))
+        .route("/withdraw", routing::post(withdraw))
+        .route("/report", routing::get(report))
+}
+
+/// Change of the cashbox content by a transaction. Cash deposits count as
+/// booked, so corrections with a negative amount take money out again.
+/// Anonymous sales are booked with the negated price that was paid in cash.
+fn cash_flow(transaction: &transaction::Model) -> i32 {
+    match transaction.kind {
+        Kind::Purchase => -transaction.amount,
+        _ => transaction.amount,
+    }
+}
+
+/// all money that went into the cashbox: cash deposits and anonymous sales
+async fn inflows(db: &impl ConnectionTrait) -> Result<Vec<(NaiveDateTime, i32)>> {
+    Ok(transaction::Entity::find()
+        .filter(
+            Condition::any()
+                .add(
+                    Condition::all()
+                        .add(transaction::Column::Kind.eq(Kind::Deposit))
+                        .add(transaction::Column::Reference.is_null()),
+                )
+                .add(
+                    Condition::all()
+                        .add(transaction::Column::Kind.eq(Kind::Purchase))
+                        .add(transaction::Column::User.is_null()),
+                ),
+        )
+        .order_by_asc(transaction::Column::CreatedAt)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|t| (t.created_at, cash_flow(&t)))
+        .collect())
+}
+
+/// Replays the cash flows and cashbox entries in chronological order.
+/// Every count resets the expected content to the counted amount. Returns the
+/// reconciliation of every count and the currently expected content.
+fn reconcile(
+    inflows: &[(NaiveDateTime, i32)],
+    entries: &[cashbox::Model],
+) -> (Vec<Reconciliation>, i32) {
+    let mut expected = 0;
+    let mut reports = Vec::new();
+    let mut inflows = inflows.iter().peekable();
+
+    for entry in entries {
+        while let Some((_, amount)) = inflows.next_if(|(at, _)| *at <= entry.created_at) {
+            expected += amount;
+        }
+        match entry.kind {
+            CashboxKind::Withdrawal => expected -= entry.amount,
+            CashboxKind::Count => {
+                reports.push(Reconciliation {
+                    counted_at: chrono::DateTime::from_utc(entry.created_at, chrono::Utc),
+                    expected,
+                    counted: entry.amount,
+                    discrepancy: entry.amount - expected,
+                });
+                expected = entry.amount;
+            }
+        }
+    }
+    expected += inflows.map(|(_, amount)| amount).sum::<i32>();
+
+    (reports, expected)
+}
+
+async fn entries(db: &impl ConnectionTrait) -> Result<Vec<cashbox::Model>> {
+    Ok(cashbox::Entity::find()
+        .order_by_asc(cashbox::Column::CreatedAt)
+        .order_by_asc(cashbox::Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// expected content of the cashbox
+async fn status(Extension(db): Extension<Db>) -> Result<Json<CashboxStatus>> {
+    let entries = entries(&db.orm).await?;
+    let (_, expected) = reconcile(&inflows(&db.orm).await?, &entries);
+    let last_count = entries
+        .into_iter()
+        .rev()
+        .find(|e| e.kind == CashboxKind::Count)
+        .map(Into::into);
+
+    Ok(Json(CashboxStatus {
+        expected,
+        last_count,
+    }))
+}
+
+async fn insert(db: &Db, kind: CashboxKind, request: CashboxRequest) -> Result<CashboxEntry> {
+    let entry = cashbox::ActiveModel {
+        kind: Set(kind),
+        amount: Set(request.amount),
+        comment: Set(request.comment),
+        ..Default::default()
+    }
+    .insert(&db.orm)
+    .await?;
+    Ok(entry.into())
+}
+
+/// records the counted content of the cashbox
+async fn count(
+    Json(request): Json<CashboxRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<(StatusCode, Json<CashboxEntry>)> {
+    let entry = insert(&db, CashboxKind::Count, request).await?;
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+/// records money taken out of the cashbox, e.g. to buy new stock
+async fn withdraw(
+    Json(request): Json<CashboxRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<(StatusCode, Json<CashboxEntry>)> {
+    let entry = insert(&db, CashboxKind::Withdrawal, request).await?;
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+/// discrepancy between expected and counted content for every count
+async fn report(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<Reconciliation>>> {
+    let (reports, _) = reconcile(&inflows(&db.orm).await?, &entries(&db.orm).await?);
+    let reports = reports
+        .into_iter()
+        .filter(|r| {
+            let day = r
+                .counted_at
+                .with_timezone(&chrono::Local)
+                .date()
+                .naive_local();
+            !matches!(query.from, Some(from) if day < from)
+                && !matches!(query.to, Some(to) if day > to)
+        })
+        .collect();
+
+    Ok(Json(reports))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2022, 4, 2).and_hms(hour, 0, 0)
+    }
+
+    fn entry(hour: u32, kind: CashboxKind, amount: i32) -> cashbox::Model {
+        cashbox::Model {
+            id: 0,
+            kind,
+            amount,
+            comment: None,
+            created_at: at(hour),
+        }
+    }
+
+    #[test]
+    fn counts_reset_expected_content() {
+        let deposit = |amount| transaction::Model {
+            id: 0,
+            kind: Kind::Deposit,
+            user: Some(1),
+            product: None,
+            amount,
+            created_at: at(3),
+            reference: None,
+            event: None,
+            group_account: None,
+        };
+        let sale = transaction::Model {
+            kind: Kind::Purchase,
+            user: None,
+            product: Some(1),
+            ..deposit(-150)
+        };
+        assert_eq!(cash_flow(&deposit(-200)), -200);
+        assert_eq!(cash_flow(&sale), 150);
+
+        let inflows = [(at(1), 1000), (at(3), 700), (at(3), -200), (at(6), 150)];
+        let entries = [
+            entry(2, CashboxKind::Count, 1000),
+            entry(4, CashboxKind::Withdrawal, 300),
+            entry(5, CashboxKind::Count, 1100),
+        ];
+
+        let (reports, expected) = reconcile(&inflows, &entries);
+
+        assert_eq!(
+            reports
+                .iter()
+                .map(|r| (r.expected, r.counted, r.discrepancy))
+                .collect::<Vec<_>>(),
+            vec![(1000, 1000, 0), (1200, 1100, -100)]
+        );
+        assert_eq!(expected, 1250);
+    }
+}
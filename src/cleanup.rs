@@ -0,0 +1,294 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{
+    extract::{Extension, Query},
+    routing, Json, Router,
+};
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{entity::*, query::*, ConnectionTrait, FromQueryResult, TransactionTrait};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Admin,
+    config::{CleanupConfig, Config},
+    entity::{
+        transaction::{self, Kind},
+        user,
+    },
+    models::{CleanupAction, CleanupQuery, CleanupReport, InactiveUser},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// how often the cleanup job runs
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub fn router() -> Router {
+    Router::new().route("/", routing::get(report))
+}
+
+/// Users without activity since `cutoff`. Active users are deactivated, users
+/// without balance are archived if enabled, even if they are inactive already.
+fn candidates(
+    users: Vec<user::Model>,
+    last_activity: &HashMap<i32, NaiveDateTime>,
+    cutoff: NaiveDateTime,
+    archive_empty: bool,
+) -> Vec<InactiveUser> {
+    users
+        .into_iter()
+        .filter(|u| u.archived_at.is_none())
+        .filter_map(|u| {
+            let last_activity = last_activity
+                .get(&u.id)
+                .copied()
+                .unwrap_or(u.created_at)
+                .max(u.created_at);
+            let action = if last_activity >= cutoff {
+                return None;
+            } else if archive_empty && u.balance == 0 {
+                CleanupAction::Archive
+            } else if u.active {
+                CleanupAction::Deactivate
+            } else {
+                return None;
+            };
+
+            Some(InactiveUser {
+                user: u.id,
+                name: u.name,
+                balance: u.balance,
+                last_activity: chrono::DateTime::from_utc(last_activity, Utc),
+                action,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, FromQueryResult)]
+struct LastActivity {
+    user: i32,
+    last_activity: NaiveDateTime,
+}
+
+async fn find_candidates(
+    db: &impl ConnectionTrait,
+    days: u32,
+    archive_empty: bool,
+) -> Result<CleanupReport> {
+    let cutoff = Utc::now().naive_utc() - chrono::Duration::days(days.into());
+
+    // only bookings made by the user count, recurring charges (spendings
+    // with a reference), received transfers and merges must not keep an
+    // account alive
+    let last_activity = transaction::Entity::find()
+        .select_only()
+        .column(transaction::Column::User)
+        .column_as(transaction::Column::CreatedAt.max(), "last_activity")
+        .filter(transaction::Column::User.is_not_null())
+        .filter(
+            Condition::any()
+                .add(transaction::Column::Kind.is_in([Kind::Purchase, Kind::Deposit]))
+                .add(
+                    Condition::all()
+                        .add(transaction::Column::Kind.eq(Kind::Transfer))
+                        .add(transaction::Column::Amount.lt(0)),
+                )
+                .add(
+                    Condition::all()
+                        .add(transaction::Column::Kind.eq(Kind::Spend))
+                        .add(transaction::Column::Reference.is_null()),
+                ),
+        )
+        .group_by(transaction::Column::User)
+        .into_model::<LastActivity>()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|row| (row.user, row.last_activity))
+        .collect();
+    let users = user::Entity::find()
+        .order_by_asc(user::Column::Id)
+        .all(db)
+        .await?;
+
+    Ok(CleanupReport {
+        cutoff: chrono::DateTime::from_utc(cutoff, Utc),
+        users: candidates(users, &last_activity, cutoff, archive_empty),
+    })
+}
+
+/// dry run of the cleanup, lists the users that would be deactivated or archived
+async fn report(
+    Query(query): Query<CleanupQuery>,
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<Config>,
+    _: Admin,
+) -> Result<Json<CleanupReport>> {
+    let days = query
+        .days
+        .or(config.cleanup.inactive_after_days)
+        .ok_or_else(|| AppError::InvalidInput("no number of days given".to_string()))?;
+    let report = find_candidates(&db.orm, days, config.cleanup.archive_empty).await?;
+    Ok(Json(report))
+}
+
+async fn cleanup(db: &Db, days: u32, archive_empty: bool) -> Result<CleanupReport> {
+    db.orm
+        .transaction::<_, CleanupReport, AppError>(|txn| {
+            Box::pin(async move {
+                let report = find_candidates(txn, days, archive_empty).await?;
+                let now = Utc::now().naive_utc();
+                for candidate in &report.users {
+                    let mut user = user::ActiveModel {
+                        id: Unchanged(candidate.user),
+                        active: Set(false),
+                        ..Default::default()
+                    };
+                    if candidate.action == CleanupAction::Archive {
+                        user.archived_at = Set(Some(now));
+                    }
+                    user.update(txn).await?;
+                }
+                Ok(report)
+            })
+        })
+        .await
+        .map_err(Into::into)
+}
+
+/// background task deactivating inactive users once a day, does nothing if
+/// no period of inactivity is configured
+pub async fn scheduler(db: Db, config: CleanupConfig) {
+    let days = match config.inactive_after_days {
+        Some(days) => days,
+        None => return,
+    };
+
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match cleanup(&db, days, config.archive_empty).await {
+            Ok(report) if report.users.is_empty() => {}
+            Ok(report) => info!("cleaned up {} inactive users", report.users.len()),
+            Err(err) => warn!("unable to clean up inactive users: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn inactive_users_are_deactivated_or_archived() {
+        let day = |d| NaiveDate::from_ymd(2022, 5, d).and_hms(12, 0, 0);
+        let user = |id, balance, active| user::Model {
+            id,
+            name: format!("user{}", id),
+            email: None,
+            created_at: day(1),
+            updated_at: day(1),
+            balance,
+            active,
+            audit: false,
+            redirect: true,
+            avatar: None,
+            age_verified: false,
+            event: None,
+            merged_into: None,
+            archived_at: None,
+            notifications: true,
+        };
+        let users = || {
+            vec![
+                user(1, 100, true),
+                user(2, 0, true),
+                user(3, 0, false),
+                user(4, 100, false),
+                user(5, 100, true),
+            ]
+        };
+        let last_activity = HashMap::from([(5, day(20))]);
+        let actions = |archive_empty| {
+            candidates(users(), &last_activity, day(10), archive_empty)
+                .into_iter()
+                .map(|u| (u.user, u.action))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            actions(false),
+            vec![
+                (1, CleanupAction::Deactivate),
+                (2, CleanupAction::Deactivate)
+            ]
+        );
+        assert_eq!(
+            actions(true),
+            vec![
+                (1, CleanupAction::Deactivate),
+                (2, CleanupAction::Archive),
+                (3, CleanupAction::Archive)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_bookings_of_the_user_count_as_activity() {
+        // whole seconds, postgres only keeps microseconds
+        let now = Utc::now().naive_utc().date().and_hms(12, 0, 0);
+        let (long_ago, yesterday) = (
+            now - chrono::Duration::days(100),
+            now - chrono::Duration::days(1),
+        );
+        for db in crate::testing::backends("cleanup").await {
+            let mut users = Vec::new();
+            for name in ["charged", "spender", "buyer", "receiver", "sender"] {
+                let user = user::ActiveModel {
+                    name: Set(name.to_owned()),
+                    balance: Set(100),
+                    created_at: Set(long_ago),
+                    ..Default::default()
+                };
+                users.push(user.insert(&db.orm).await.unwrap().id);
+            }
+            let bookings = [
+                (users[0], Kind::Purchase, -100, None, long_ago),
+                (users[0], Kind::Spend, -100, Some("rent 2022-05"), yesterday),
+                (users[1], Kind::Spend, -100, None, yesterday),
+                (users[2], Kind::Purchase, -100, None, long_ago),
+                (users[2], Kind::Purchase, -100, None, yesterday),
+                (users[3], Kind::Purchase, -100, None, long_ago),
+                (users[4], Kind::Transfer, -100, None, yesterday),
+                (users[3], Kind::Transfer, 100, None, yesterday),
+            ];
+            for (user, kind, amount, reference, created_at) in bookings {
+                transaction::ActiveModel {
+                    kind: Set(kind),
+                    user: Set(Some(user)),
+                    amount: Set(amount),
+                    reference: Set(reference.map(ToOwned::to_owned)),
+                    created_at: Set(created_at),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            let report = find_candidates(&db.orm, 30, false).await.unwrap();
+            assert_eq!(
+                report
+                    .users
+                    .iter()
+                    .map(|u| (u.user, u.last_activity.naive_utc()))
+                    .collect::<Vec<_>>(),
+                vec![(users[0], long_ago), (users[3], long_ago)]
+            );
+        }
+    }
+}
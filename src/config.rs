@@ -1,16 +1,19 @@
 use std::{
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
 };
 
-use crate::models::DefaultProduct;
+use crate::{entity::webhook_event, models::DefaultProduct};
 use serde::Deserialize;
 
 const CONFIG_FILENAME: &str = "config.toml";
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
+    #[error("unable to read config file: {0}")]
     IoError(String),
+    #[error("unable to parse config file: {0}")]
     Parser(String),
 }
 
@@ -37,6 +40,24 @@ pub struct Config {
     pub storage: StorageConfig,
     #[serde(default, rename = "")]
     pub default_product: DefaultProductConfig,
+    #[serde(default)]
+    pub nutrition: NutritionConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub bank: BankConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -61,16 +82,73 @@ fn default_listen() -> SocketAddr {
 pub struct StorageConfig {
     #[serde(default = "default_database")]
     pub database: String,
+    /// maximum number of open connections
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// sqlite only
+    #[serde(default)]
+    pub journal_mode: JournalMode,
+    /// sqlite only
+    #[serde(default)]
+    pub synchronous: Synchronous,
+    /// milliseconds to wait for a locked sqlite database before giving up
+    #[serde(default = "default_busy_timeout")]
+    pub busy_timeout_ms: u64,
+    /// let sqlite enforce the foreign keys declared in the schema
+    #[serde(default = "default_foreign_keys")]
+    pub foreign_keys: bool,
 }
 
 fn default_database() -> String {
     "sqlite://database.sqlite".to_owned()
 }
 
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_busy_timeout() -> u64 {
+    5000
+}
+
+fn default_foreign_keys() -> bool {
+    true
+}
+
+/// `PRAGMA journal_mode` of sqlite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    #[default]
+    Wal,
+    Off,
+}
+
+/// `PRAGMA synchronous` of sqlite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    #[default]
+    Full,
+    Extra,
+}
+
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
             database: default_database(),
+            max_connections: default_max_connections(),
+            busy_timeout_ms: default_busy_timeout(),
+            foreign_keys: default_foreign_keys(),
+
+            journal_mode: Default::default(),
+            synchronous: Default::default(),
         }
     }
 }
@@ -125,6 +203,441 @@ impl From<DefaultProductConfig> for DefaultProduct {
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct NutritionConfig {
+    /// daily caffeine intake in mg, the purchase reaching it returns a warning
+    pub caffeine_warning: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AdminConfig {
+    /// bearer token required for privileged operations
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct BankConfig {
+    #[serde(default)]
+    pub csv: BankCsvConfig,
+}
+
+/// layout of bank statements exported as csv
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BankCsvConfig {
+    #[serde(default = "default_csv_delimiter")]
+    pub delimiter: char,
+    /// `strftime` like format of the booking date
+    #[serde(default = "default_csv_date_format")]
+    pub date_format: String,
+    #[serde(default = "default_csv_decimal_separator")]
+    pub decimal_separator: char,
+    /// column names
+    #[serde(default = "default_csv_date")]
+    pub date: String,
+    #[serde(default = "default_csv_amount")]
+    pub amount: String,
+    #[serde(default = "default_csv_reference")]
+    pub reference: String,
+    #[serde(default = "default_csv_name")]
+    pub name: String,
+    /// column containing a unique id of the line, if the bank provides one
+    pub id: Option<String>,
+}
+
+fn default_csv_delimiter() -> char {
+    ','
+}
+
+fn default_csv_date_format() -> String {
+    "%Y-%m-%d".to_owned()
+}
+
+fn default_csv_decimal_separator() -> char {
+    '.'
+}
+
+fn default_csv_date() -> String {
+    "date".to_owned()
+}
+
+fn default_csv_amount() -> String {
+    "amount".to_owned()
+}
+
+fn default_csv_reference() -> String {
+    "reference".to_owned()
+}
+
+fn default_csv_name() -> String {
+    "name".to_owned()
+}
+
+impl Default for BankCsvConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: default_csv_delimiter(),
+            date_format: default_csv_date_format(),
+            decimal_separator: default_csv_decimal_separator(),
+            date: default_csv_date(),
+            amount: default_csv_amount(),
+            reference: default_csv_reference(),
+            name: default_csv_name(),
+            id: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CleanupConfig {
+    /// users without any activity for this many days are deactivated
+    pub inactive_after_days: Option<u32>,
+    /// inactive users without balance are archived as well
+    #[serde(default)]
+    pub archive_empty: bool,
+}
+
+/// emails sent to users, nothing is sent without `smtp_host`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotificationConfig {
+    pub smtp_host: Option<String>,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    /// upgrade the connection with STARTTLS, disable for a local smtp sink
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    /// balance in cent below which a user is reminded once until the next deposit
+    pub low_balance: Option<i32>,
+    /// days between two reminders while the balance is negative
+    pub debt_reminder_days: Option<u32>,
+    /// summary of the last month sent at the beginning of every month
+    #[serde(default)]
+    pub monthly_statement: bool,
+    #[serde(default)]
+    pub templates: NotificationTemplates,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+fn default_mail_from() -> String {
+    "Matekasse <matekasse@localhost>".to_owned()
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            smtp_port: default_smtp_port(),
+            starttls: default_starttls(),
+            from: default_mail_from(),
+
+            smtp_host: Default::default(),
+            smtp_username: Default::default(),
+            smtp_password: Default::default(),
+            low_balance: Default::default(),
+            debt_reminder_days: Default::default(),
+            monthly_statement: Default::default(),
+            templates: Default::default(),
+        }
+    }
+}
+
+/// Subject and body of every kind of email. `{name}`, `{balance}` and
+/// `{threshold}` are replaced in all of them, statements additionally know
+/// `{month}`, `{spent}` and `{deposited}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotificationTemplates {
+    #[serde(default = "default_low_balance_template")]
+    pub low_balance: Template,
+    #[serde(default = "default_debt_template")]
+    pub debt: Template,
+    #[serde(default = "default_statement_template")]
+    pub statement: Template,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+fn default_low_balance_template() -> Template {
+    Template {
+        subject: "Your balance is low".to_owned(),
+        body: "Hi {name},\n\nyour balance is down to {balance} EUR. Please top it up soon.\n"
+            .to_owned(),
+    }
+}
+
+fn default_debt_template() -> Template {
+    Template {
+        subject: "Please settle your debt".to_owned(),
+        body: "Hi {name},\n\nyour balance is {balance} EUR. Please deposit some money.\n"
+            .to_owned(),
+    }
+}
+
+fn default_statement_template() -> Template {
+    Template {
+        subject: "Your statement for {month}".to_owned(),
+        body: "Hi {name},\n\nin {month} you spent {spent} EUR and deposited {deposited} EUR.\nYour balance is {balance} EUR.\n"
+            .to_owned(),
+    }
+}
+
+impl Default for NotificationTemplates {
+    fn default() -> Self {
+        Self {
+            low_balance: default_low_balance_template(),
+            debt: default_debt_template(),
+            statement: default_statement_template(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WebhookConfig {
+    /// failed deliveries are retried with an increasing delay this many times
+    #[serde(default = "default_webhook_attempts")]
+    pub max_attempts: i32,
+    /// delivered and given up deliveries are deleted after this many days
+    #[serde(default = "default_webhook_retention")]
+    pub retention_days: u32,
+    #[serde(default)]
+    pub subscriptions: Vec<WebhookSubscription>,
+}
+
+fn default_webhook_attempts() -> i32 {
+    10
+}
+
+fn default_webhook_retention() -> u32 {
+    30
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_webhook_attempts(),
+            retention_days: default_webhook_retention(),
+            subscriptions: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WebhookSubscription {
+    pub url: String,
+    /// key of the HMAC-SHA256 signature of every payload
+    pub secret: String,
+    /// events posted to the url, all of them if empty
+    #[serde(default)]
+    pub events: Vec<webhook_event::Kind>,
+}
+
+/// broker the sales are published to, nothing is published without `host`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MqttConfig {
+    pub host: Option<String>,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub topics: MqttTopics,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "matekasse".to_owned()
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            port: default_mqtt_port(),
+            client_id: default_mqtt_client_id(),
+
+            host: Default::default(),
+            username: Default::default(),
+            password: Default::default(),
+            topics: Default::default(),
+        }
+    }
+}
+
+/// topics of the published messages, `{id}` is replaced by the product id
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MqttTopics {
+    #[serde(default = "default_purchases_topic")]
+    pub purchases: String,
+    /// retained state of every product including its stock
+    #[serde(default = "default_products_topic")]
+    pub products: String,
+    /// retained number of purchases and revenue of the current day
+    #[serde(default = "default_totals_topic")]
+    pub totals: String,
+}
+
+fn default_purchases_topic() -> String {
+    "matekasse/purchases".to_owned()
+}
+
+fn default_products_topic() -> String {
+    "matekasse/products/{id}".to_owned()
+}
+
+fn default_totals_topic() -> String {
+    "matekasse/totals".to_owned()
+}
+
+impl Default for MqttTopics {
+    fn default() -> Self {
+        Self {
+            purchases: default_purchases_topic(),
+            products: default_products_topic(),
+            totals: default_totals_topic(),
+        }
+    }
+}
+
+/// copies of the database written while the server is running
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BackupConfig {
+    #[serde(default = "default_backup_directory")]
+    pub directory: String,
+    /// hours between two scheduled backups, none are made if unset
+    pub interval_hours: Option<u32>,
+    /// number of backups kept, older ones are deleted
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+fn default_backup_directory() -> String {
+    "backups".to_owned()
+}
+
+fn default_backup_keep() -> usize {
+    14
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_backup_directory(),
+            keep: default_backup_keep(),
+
+            interval_hours: Default::default(),
+        }
+    }
+}
+
+/// account names used in exported accounting journals
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JournalConfig {
+    #[serde(default = "default_journal_currency")]
+    pub currency: String,
+    /// parent of the liability account of every user
+    #[serde(default = "default_journal_members")]
+    pub members: String,
+    /// parent of the liability account of every group account
+    #[serde(default = "default_journal_groups")]
+    pub groups: String,
+    #[serde(default = "default_journal_cashbox")]
+    pub cashbox: String,
+    /// account receiving deposits made by bank transfer
+    #[serde(default = "default_journal_bank")]
+    pub bank: String,
+    /// revenue account of products without an entry in `products`
+    #[serde(default = "default_journal_revenue")]
+    pub revenue: String,
+    /// clearing account of transfers whose counterpart is not exported
+    #[serde(default = "default_journal_transfers")]
+    pub transfers: String,
+    /// counterpart of starting balances from a user import or a mete audit
+    #[serde(default = "default_journal_opening")]
+    pub opening: String,
+    /// revenue account per product id, so renaming a product keeps its account
+    #[serde(default, deserialize_with = "product_accounts")]
+    pub products: HashMap<i32, String>,
+}
+
+/// keys of toml tables are always strings
+fn product_accounts<'de, D>(deserializer: D) -> Result<HashMap<i32, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, account)| {
+            id.parse()
+                .map(|id| (id, account))
+                .map_err(|_| serde::de::Error::custom(format!("invalid product id {:?}", id)))
+        })
+        .collect()
+}
+
+fn default_journal_currency() -> String {
+    "EUR".to_owned()
+}
+
+fn default_journal_members() -> String {
+    "Liabilities:Members".to_owned()
+}
+
+fn default_journal_groups() -> String {
+    "Liabilities:Groups".to_owned()
+}
+
+fn default_journal_cashbox() -> String {
+    "Assets:Cashbox".to_owned()
+}
+
+fn default_journal_bank() -> String {
+    "Assets:Bank".to_owned()
+}
+
+fn default_journal_revenue() -> String {
+    "Income:Drinks".to_owned()
+}
+
+fn default_journal_transfers() -> String {
+    "Equity:Transfers".to_owned()
+}
+
+fn default_journal_opening() -> String {
+    "Equity:Opening-Balances".to_owned()
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            currency: default_journal_currency(),
+            members: default_journal_members(),
+            groups: default_journal_groups(),
+            cashbox: default_journal_cashbox(),
+            bank: default_journal_bank(),
+            revenue: default_journal_revenue(),
+            transfers: default_journal_transfers(),
+            opening: default_journal_opening(),
+            products: Default::default(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -139,4 +652,15 @@ mod tests {
 
         assert_eq!(super::Config::default(), default_config);
     }
+
+    #[test]
+    fn journal_accounts_are_keyed_by_product_id() {
+        let config: super::Config =
+            toml::from_str("[journal.products]\n7 = \"Income:Drinks:Mate\"\n").unwrap();
+
+        assert_eq!(config.journal.products[&7], "Income:Drinks:Mate");
+        assert!(
+            toml::from_str::<super::Config>("[journal.products]\nMate = \"Income\"\n").is_err()
+        );
+    }
 }
@@ -15,11 +15,14 @@ pub mod product {
         pub alcohol: Option<i32>,
         pub energy: Option<i32>,
         pub sugar: Option<i32>,
+        pub volume: Option<i32>,
         pub price: i32,
         pub created_at: DateTime,
         pub updated_at: DateTime,
         pub active: bool,
         pub image: Option<i32>,
+        pub age_restricted: bool,
+        pub stock: Option<i32>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -43,11 +46,14 @@ pub mod product {
                 alcohol: model.alcohol,
                 energy: model.energy,
                 sugar: model.sugar,
+                volume: model.volume,
                 price: model.price,
                 created_at: chrono::DateTime::from_utc(model.created_at, chrono::Utc),
                 updated_at: chrono::DateTime::from_utc(model.updated_at, chrono::Utc),
                 active: model.active,
                 image: model.image,
+                age_restricted: model.age_restricted,
+                stock: model.stock,
             }
         }
     }
@@ -72,11 +78,14 @@ pub mod product {
             unwrap_or_err!(value.alcohol);
             unwrap_or_err!(value.energy);
             unwrap_or_err!(value.sugar);
+            unwrap_or_err!(value.volume);
             unwrap_or_err!(value.price);
             unwrap_or_err!(value.created_at);
             unwrap_or_err!(value.updated_at);
             unwrap_or_err!(value.active);
             unwrap_or_err!(value.image);
+            unwrap_or_err!(value.age_restricted);
+            unwrap_or_err!(value.stock);
 
             Ok(Product {
                 id,
@@ -85,11 +94,14 @@ pub mod product {
                 alcohol,
                 energy,
                 sugar,
+                volume,
                 price,
                 created_at: chrono::DateTime::from_utc(created_at, chrono::Utc),
                 updated_at: chrono::DateTime::from_utc(updated_at, chrono::Utc),
                 active,
                 image,
+                age_restricted,
+                stock,
             })
         }
     }
@@ -116,6 +128,14 @@ pub mod user {
         pub audit: bool,
         pub redirect: bool,
         pub avatar: Option<i32>,
+        pub age_verified: bool,
+        /// temporary users belong to an event
+        pub event: Option<i32>,
+        /// archived duplicate, everything was moved to this user
+        pub merged_into: Option<i32>,
+        pub archived_at: Option<DateTime>,
+        /// user wants to receive emails
+        pub notifications: bool,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -144,6 +164,13 @@ pub mod user {
                 audit: model.audit,
                 redirect: model.redirect,
                 avatar: model.avatar,
+                age_verified: model.age_verified,
+                event: model.event,
+                merged_into: model.merged_into,
+                archived_at: model
+                    .archived_at
+                    .map(|archived_at| chrono::DateTime::from_utc(archived_at, chrono::Utc)),
+                notifications: model.notifications,
             }
         }
     }
@@ -172,6 +199,11 @@ pub mod user {
             unwrap_or_err!(value.audit);
             unwrap_or_err!(value.redirect);
             unwrap_or_err!(value.avatar);
+            unwrap_or_err!(value.age_verified);
+            unwrap_or_err!(value.event);
+            unwrap_or_err!(value.merged_into);
+            unwrap_or_err!(value.archived_at);
+            unwrap_or_err!(value.notifications);
 
             Ok(User {
                 id,
@@ -185,7 +217,508 @@ pub mod user {
                 audit,
                 redirect,
                 avatar,
+                age_verified,
+                event,
+                merged_into,
+                archived_at: archived_at
+                    .map(|archived_at| chrono::DateTime::from_utc(archived_at, chrono::Utc)),
+                notifications,
             })
         }
     }
 }
+
+pub mod transaction {
+    use crate::models::LedgerEntry;
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "lowercase")]
+    pub enum Kind {
+        #[sea_orm(string_value = "purchase")]
+        Purchase,
+        #[sea_orm(string_value = "deposit")]
+        Deposit,
+        #[sea_orm(string_value = "spend")]
+        Spend,
+        #[sea_orm(string_value = "transfer")]
+        Transfer,
+        /// Another user was merged into `user`, the history of the other user
+        /// now belongs to `user`. As that history already adds up to the moved
+        /// balance, `amount` is 0 and the balance is only named in `reference`.
+        #[sea_orm(string_value = "merge")]
+        Merge,
+    }
+
+    /// A single entry in the ledger, `amount` is the change of the users balance
+    /// in cent. If `group_account` is set, the balance of the group changed and
+    /// `user` is the acting member.
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "transactions")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub kind: Kind,
+        pub user: Option<i32>,
+        pub product: Option<i32>,
+        pub amount: i32,
+        pub created_at: DateTime,
+        pub reference: Option<String>,
+        /// event the purchasing user belonged to
+        pub event: Option<i32>,
+        pub group_account: Option<i32>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl From<Model> for LedgerEntry {
+        fn from(model: Model) -> Self {
+            LedgerEntry {
+                id: model.id,
+                kind: model.kind,
+                user: model.user,
+                product: model.product,
+                amount: model.amount,
+                created_at: chrono::DateTime::from_utc(model.created_at, chrono::Utc),
+                group_account: model.group_account,
+                reference: model.reference,
+            }
+        }
+    }
+}
+
+pub mod event {
+    use crate::models::Event;
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "event")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        pub name: String,
+        pub created_at: DateTime,
+        pub closed_at: Option<DateTime>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl From<Model> for Event {
+        fn from(model: Model) -> Self {
+            Event {
+                id: model.id,
+                name: model.name,
+                created_at: chrono::DateTime::from_utc(model.created_at, chrono::Utc),
+                closed_at: model
+                    .closed_at
+                    .map(|closed_at| chrono::DateTime::from_utc(closed_at, chrono::Utc)),
+            }
+        }
+    }
+}
+
+pub mod group_account {
+    use sea_orm::{entity::prelude::*, ActiveValue};
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "group_account")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        pub name: String,
+        pub balance: i32,
+        pub created_at: DateTime,
+        pub updated_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {
+        fn before_save(self, _: bool) -> Result<Self, DbErr> {
+            Ok(Self {
+                updated_at: ActiveValue::set(chrono::Utc::now().naive_utc()),
+                ..self
+            })
+        }
+    }
+}
+
+pub mod group_member {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "group_member")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub group_account: i32,
+        pub user: i32,
+        /// maximum the member may charge to the group in cent
+        pub cap: Option<i32>,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod recurring_charge {
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "lowercase")]
+    pub enum Interval {
+        #[sea_orm(string_value = "daily")]
+        Daily,
+        #[sea_orm(string_value = "weekly")]
+        Weekly,
+        /// on the same day of every month, or the last day of shorter months
+        #[sea_orm(string_value = "monthly")]
+        Monthly,
+    }
+
+    /// `amount` in cent is charged to every target user and the group every
+    /// period, starting at `starts_on`
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "recurring_charge")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub name: String,
+        pub amount: i32,
+        pub interval: Interval,
+        pub starts_on: Date,
+        pub group_account: Option<i32>,
+        pub active: bool,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod recurring_charge_user {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "recurring_charge_user")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub charge: i32,
+        pub user: i32,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod recurring_booking {
+    use sea_orm::entity::prelude::*;
+
+    /// a period of a recurring charge that was booked
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "recurring_booking")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub charge: i32,
+        pub period: Date,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod notification {
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(
+        Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "snake_case")]
+    pub enum Kind {
+        /// balance fell below the configured threshold
+        #[sea_orm(string_value = "low_balance")]
+        LowBalance,
+        /// periodic reminder while the balance is negative
+        #[sea_orm(string_value = "debt")]
+        Debt,
+        /// summary of the last month
+        #[sea_orm(string_value = "statement")]
+        Statement,
+    }
+
+    /// an email sent to a user
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "notification")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub user: i32,
+        pub kind: Kind,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod webhook_event {
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(
+        Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "snake_case")]
+    pub enum Kind {
+        #[sea_orm(string_value = "purchase")]
+        Purchase,
+        #[sea_orm(string_value = "deposit")]
+        Deposit,
+        #[sea_orm(string_value = "transfer")]
+        Transfer,
+        #[sea_orm(string_value = "user_created")]
+        UserCreated,
+        /// the last item of a product in stock was sold
+        #[sea_orm(string_value = "out_of_stock")]
+        OutOfStock,
+    }
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "webhook_event")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub kind: Kind,
+        /// json encoded data of the event
+        pub payload: String,
+        /// deliveries to all subscribers were queued
+        pub dispatched: bool,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod webhook_delivery {
+    use sea_orm::entity::prelude::*;
+
+    /// an event to be posted to a single subscriber
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "webhook_delivery")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub event: i32,
+        pub url: String,
+        pub attempts: i32,
+        pub next_attempt_at: DateTime,
+        pub delivered_at: Option<DateTime>,
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod cashbox {
+    use crate::models::CashboxEntry;
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "lowercase")]
+    pub enum Kind {
+        /// `amount` is the counted content of the cashbox
+        #[sea_orm(string_value = "count")]
+        Count,
+        /// `amount` was taken out of the cashbox
+        #[sea_orm(string_value = "withdrawal")]
+        Withdrawal,
+    }
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "cashbox")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub kind: Kind,
+        pub amount: i32,
+        pub comment: Option<String>,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl From<Model> for CashboxEntry {
+        fn from(model: Model) -> Self {
+            CashboxEntry {
+                id: model.id,
+                kind: model.kind,
+                amount: model.amount,
+                comment: model.comment,
+                created_at: chrono::DateTime::from_utc(model.created_at, chrono::Utc),
+            }
+        }
+    }
+}
+
+pub mod bank_transaction {
+    use crate::models::BankTransaction;
+    use sea_orm::{entity::prelude::*, ActiveValue};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    #[serde(rename_all = "lowercase")]
+    pub enum Status {
+        #[sea_orm(string_value = "pending")]
+        Pending,
+        #[sea_orm(string_value = "accepted")]
+        Accepted,
+        #[sea_orm(string_value = "rejected")]
+        Rejected,
+    }
+
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "bank_transaction")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        pub bank_id: String,
+        pub booked_at: Date,
+        pub amount: i32,
+        pub reference: String,
+        pub name: Option<String>,
+        pub status: Status,
+        /// proposed user until accepted, booked user afterwards
+        pub user: Option<i32>,
+        /// ledger entry of the booked deposit
+        pub transaction: Option<i32>,
+        pub created_at: DateTime,
+        pub updated_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {
+        fn before_save(self, _: bool) -> Result<Self, DbErr> {
+            Ok(Self {
+                updated_at: ActiveValue::set(chrono::Utc::now().naive_utc()),
+                ..self
+            })
+        }
+    }
+
+    impl From<Model> for BankTransaction {
+        fn from(model: Model) -> Self {
+            BankTransaction {
+                id: model.id,
+                bank_id: model.bank_id,
+                booked_at: model.booked_at,
+                amount: model.amount,
+                reference: model.reference,
+                name: model.name,
+                status: model.status,
+                user: model.user,
+                transaction: model.transaction,
+            }
+        }
+    }
+}
+
+pub mod barcode {
+    use sea_orm::entity::prelude::*;
+
+    /// a barcode belongs either to a user or a product
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "barcode")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        pub code: String,
+        pub user: Option<i32>,
+        pub product: Option<i32>,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod mete_import {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, EnumIter, DeriveActiveEnum)]
+    #[sea_orm(rs_type = "String", db_type = "String(None)")]
+    pub enum Kind {
+        #[sea_orm(string_value = "user")]
+        User,
+        #[sea_orm(string_value = "drink")]
+        Drink,
+        #[sea_orm(string_value = "barcode")]
+        Barcode,
+        #[sea_orm(string_value = "audit")]
+        Audit,
+    }
+
+    /// maps a record of a mete installation to the local row it was imported as
+    #[derive(Debug, Clone, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "mete_import")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub kind: Kind,
+        pub mete_id: String,
+        pub local_id: i32,
+        pub created_at: DateTime,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use sea_orm::{entity::*, query::*, sea_query::Expr, ConnectionTrait, TransactionTrait};
+
+use crate::{
+    auth::Admin,
+    entity::{
+        event,
+        transaction::{self, Kind},
+        user,
+    },
+    live::Live,
+    models::{
+        Event, EventCreateRequest, EventSettlement, EventUserSettlement, LiveEvent, User,
+        UserCreateRequest,
+    },
+    stats::{product_names, product_stats},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", routing::get(get_all).post(create))
+        .route("/:id", routing::get(get))
+        .route("/:id/users", routing::get(users).post(create_user))
+        .route("/:id/settlement", routing::get(settlement))
+        .route("/:id/close", routing::post(close))
+}
+
+async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<Event>>> {
+    let events = event::Entity::find()
+        .order_by_asc(event::Column::CreatedAt)
+        .all(&db.orm)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(events))
+}
+
+async fn create(
+    Json(request): Json<EventCreateRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<(StatusCode, Json<Event>)> {
+    let event = event::ActiveModel {
+        name: Set(request.name),
+        ..Default::default()
+    }
+    .insert(&db.orm)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(event.into())))
+}
+
+async fn find(db: &impl ConnectionTrait, id: i32) -> Result<event::Model> {
+    event::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFount)
+}
+
+/// fails with a conflict if the event is already closed
+pub(crate) async fn ensure_open(db: &impl ConnectionTrait, id: i32) -> Result<event::Model> {
+    let event = find(db, id).await?;
+    if event.closed_at.is_some() {
+        return Err(AppError::Conflict);
+    }
+    Ok(event)
+}
+
+async fn get(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<Json<Event>> {
+    Ok(Json(find(&db.orm, id).await?.into()))
+}
+
+/// temporary users of the event
+async fn users(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<Json<Vec<User>>> {
+    find(&db.orm, id).await?;
+    let users = user::Entity::find()
+        .filter(user::Column::Event.eq(id))
+        .all(&db.orm)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(users))
+}
+
+/// creates a temporary user, which is deactivated when the event is closed
+async fn create_user(
+    Path(id): Path<i32>,
+    Json(user): Json<UserCreateRequest>,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
+) -> Result<(StatusCode, Json<User>)> {
+    if user.age_verified.is_some() && admin.is_none() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let user = db
+        .orm
+        .transaction::<_, User, AppError>(|txn| {
+            Box::pin(async move {
+                ensure_open(txn, id).await?;
+                crate::user::insert(txn, user, Some(id), None)
+                    .await
+                    .map(Into::into)
+            })
+        })
+        .await?;
+
+    live.publish([LiveEvent::User(user.clone())]);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// what every temporary user spent and still owes, and the sales per product
+fn settle(
+    event: event::Model,
+    users: Vec<user::Model>,
+    purchases: &[transaction::Model],
+    products: &HashMap<i32, String>,
+) -> EventSettlement {
+    let mut spent = HashMap::new();
+    for t in purchases {
+        if let Some(user) = t.user {
+            *spent.entry(user).or_insert(0) -= t.amount;
+        }
+    }
+
+    let users = users
+        .into_iter()
+        .map(|u| EventUserSettlement {
+            user: u.id,
+            spent: spent.get(&u.id).copied().unwrap_or(0),
+            owes: (-u.balance).max(0),
+            balance: u.balance,
+            name: u.name,
+        })
+        .collect::<Vec<_>>();
+
+    EventSettlement {
+        event: event.into(),
+        revenue: -purchases.iter().map(|t| t.amount).sum::<i32>(),
+        owed: users.iter().map(|u| u.owes).sum(),
+        users,
+        products: product_stats(purchases, products),
+    }
+}
+
+async fn settlement_of(db: &impl ConnectionTrait, event: event::Model) -> Result<EventSettlement> {
+    let users = user::Entity::find()
+        .filter(user::Column::Event.eq(event.id))
+        .order_by_asc(user::Column::Name)
+        .all(db)
+        .await?;
+    let purchases = transaction::Entity::find()
+        .filter(transaction::Column::Kind.eq(Kind::Purchase))
+        .filter(transaction::Column::Event.eq(event.id))
+        .all(db)
+        .await?;
+
+    Ok(settle(event, users, &purchases, &product_names(db).await?))
+}
+
+/// preview of the settlement, also available after the event was closed
+async fn settlement(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<EventSettlement>> {
+    let event = find(&db.orm, id).await?;
+    Ok(Json(settlement_of(&db.orm, event).await?))
+}
+
+/// closes the event, deactivates its temporary users and returns the settlement
+async fn close(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    _: Admin,
+) -> Result<Json<EventSettlement>> {
+    let (settlement, users) = db
+        .orm
+        .transaction::<_, (EventSettlement, Vec<user::Model>), AppError>(|txn| {
+            Box::pin(async move {
+                let mut event = ensure_open(txn, id).await?.into_active_model();
+                event.closed_at = Set(Some(chrono::Utc::now().naive_utc()));
+                let event = event.update(txn).await?;
+
+                user::Entity::update_many()
+                    .col_expr(user::Column::Active, Expr::value(false))
+                    .filter(user::Column::Event.eq(id))
+                    .exec(txn)
+                    .await?;
+                let users = user::Entity::find()
+                    .filter(user::Column::Event.eq(id))
+                    .all(txn)
+                    .await?;
+
+                Ok((settlement_of(txn, event).await?, users))
+            })
+        })
+        .await?;
+
+    live.publish(users.into_iter().map(|u| LiveEvent::User(u.into())));
+    Ok(Json(settlement))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn settlement_sums_spendings_and_debts() {
+        let now = Utc::now().naive_utc();
+        let event = event::Model {
+            id: 1,
+            name: "camp".to_string(),
+            created_at: now,
+            closed_at: None,
+        };
+        let user = |id, name: &str, balance| user::Model {
+            id,
+            name: name.to_string(),
+            email: None,
+            created_at: now,
+            updated_at: now,
+            balance,
+            active: true,
+            audit: false,
+            redirect: true,
+            avatar: None,
+            age_verified: false,
+            event: Some(1),
+            merged_into: None,
+            archived_at: None,
+            notifications: true,
+        };
+        let purchase = |user, product, amount| transaction::Model {
+            id: 0,
+            kind: Kind::Purchase,
+            user: Some(user),
+            product: Some(product),
+            amount,
+            created_at: now,
+            reference: None,
+            event: Some(1),
+            group_account: None,
+        };
+        let products = HashMap::from([(1, "Club-Mate".to_string()), (2, "Beer".to_string())]);
+
+        let settlement = settle(
+            event,
+            vec![user(1, "alice", -300), user(2, "bob", 50)],
+            &[
+                purchase(1, 1, -150),
+                purchase(1, 1, -150),
+                purchase(2, 2, -200),
+            ],
+            &products,
+        );
+
+        assert_eq!(
+            settlement
+                .users
+                .iter()
+                .map(|u| (u.name.as_str(), u.spent, u.owes))
+                .collect::<Vec<_>>(),
+            vec![("alice", 300, 300), ("bob", 200, 0)]
+        );
+        assert_eq!(
+            settlement
+                .products
+                .iter()
+                .map(|p| (p.name.as_str(), p.count, p.revenue))
+                .collect::<Vec<_>>(),
+            vec![("Club-Mate", 2, 300), ("Beer", 1, 200)]
+        );
+        assert_eq!((settlement.revenue, settlement.owed), (500, 300));
+    }
+
+    #[tokio::test]
+    async fn closing_publishes_the_deactivated_users() {
+        for db in crate::testing::backends("events_close").await {
+            let event = event::ActiveModel {
+                name: Set("camp".to_owned()),
+                created_at: Set(Utc::now().naive_utc()),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            for (name, event) in [("guest", Some(event.id)), ("alice", None)] {
+                user::ActiveModel {
+                    name: Set(name.to_owned()),
+                    event: Set(event),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            let live = Live::default();
+            let mut events = live.subscribe();
+            close(
+                Path(event.id),
+                Extension(db),
+                Extension(live.clone()),
+                Admin,
+            )
+            .await
+            .unwrap();
+
+            assert!(matches!(
+                events.try_recv(),
+                Ok(LiveEvent::User(u)) if u.name == "guest" && !u.active
+            ));
+            assert!(events.try_recv().is_err());
+        }
+    }
+}
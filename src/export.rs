@@ -0,0 +1,348 @@
+use std::path::PathBuf;
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Extension, Query},
+    http::header::CONTENT_TYPE,
+    response::{Headers, IntoResponse},
+    routing, Router,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use eyre::{eyre, Context};
+use sea_orm::{entity::*, query::*};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
+
+use crate::{
+    auth::Admin,
+    entity::{product, transaction, user},
+    journal,
+    stats::in_range,
+    storage::Db,
+};
+
+/// number of rows fetched from the database at once
+const PAGE_SIZE: usize = 500;
+
+/// number of encoded pages buffered while the client is reading
+const BUFFER: usize = 4;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/users", routing::get(users))
+        .route("/products", routing::get(products))
+        .route("/transactions", routing::get(transactions))
+        .route("/journal", routing::get(journal::get))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// one json object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExportKind {
+    Users,
+    Products,
+    Transactions,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// first day (inclusive, local time) of exported transactions
+    pub from: Option<NaiveDate>,
+    /// last day (inclusive, local time) of exported transactions
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct UserRow {
+    id: i32,
+    name: String,
+    email: Option<String>,
+    balance: i32,
+    active: bool,
+    audit: bool,
+    redirect: bool,
+    age_verified: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl From<user::Model> for UserRow {
+    fn from(model: user::Model) -> Self {
+        UserRow {
+            id: model.id,
+            name: model.name,
+            email: model.email,
+            balance: model.balance,
+            active: model.active,
+            audit: model.audit,
+            redirect: model.redirect,
+            age_verified: model.age_verified,
+            created_at: DateTime::from_utc(model.created_at, Utc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ProductRow {
+    id: i32,
+    name: String,
+    price: i32,
+    caffeine: Option<i32>,
+    alcohol: Option<i32>,
+    energy: Option<i32>,
+    sugar: Option<i32>,
+    volume: Option<i32>,
+    stock: Option<i32>,
+    active: bool,
+    age_restricted: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl From<product::Model> for ProductRow {
+    fn from(model: product::Model) -> Self {
+        ProductRow {
+            id: model.id,
+            name: model.name,
+            price: model.price,
+            caffeine: model.caffeine,
+            alcohol: model.alcohol,
+            energy: model.energy,
+            sugar: model.sugar,
+            volume: model.volume,
+            stock: model.stock,
+            active: model.active,
+            age_restricted: model.age_restricted,
+            created_at: DateTime::from_utc(model.created_at, Utc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TransactionRow {
+    id: i32,
+    created_at: DateTime<Utc>,
+    kind: transaction::Kind,
+    user: Option<i32>,
+    product: Option<i32>,
+    amount: i32,
+    reference: Option<String>,
+    group_account: Option<i32>,
+    event: Option<i32>,
+}
+
+impl From<transaction::Model> for TransactionRow {
+    fn from(model: transaction::Model) -> Self {
+        TransactionRow {
+            id: model.id,
+            created_at: DateTime::from_utc(model.created_at, Utc),
+            kind: model.kind,
+            user: model.user,
+            product: model.product,
+            amount: model.amount,
+            reference: model.reference,
+            group_account: model.group_account,
+            event: model.event,
+        }
+    }
+}
+
+/// encodes a single record, csv headers are written in front of the first one
+fn encode<T: Serialize>(format: ExportFormat, record: &T, first: bool) -> eyre::Result<Vec<u8>> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(first)
+                .from_writer(Vec::new());
+            writer.serialize(record)?;
+            Ok(writer.into_inner()?)
+        }
+        ExportFormat::Jsonl => {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            Ok(line)
+        }
+    }
+}
+
+/// Streams the rows of a query encoded in the given format through a channel.
+/// The rows are fetched page by page in a separate task, so neither the server
+/// nor the cli has to keep the whole table in memory.
+fn spawn_export<E, R>(
+    db: Db,
+    select: Select<E>,
+    format: ExportFormat,
+) -> mpsc::Receiver<std::io::Result<Bytes>>
+where
+    E: EntityTrait,
+    E::Model: Into<R> + Send + Sync,
+    R: Serialize,
+{
+    let (tx, rx) = mpsc::channel(BUFFER);
+    tokio::spawn(async move {
+        let result = async {
+            let mut pages = select.paginate(&db.orm, PAGE_SIZE);
+            let mut first = true;
+            while let Some(rows) = pages.fetch_and_next().await? {
+                let mut data = Vec::new();
+                for row in rows {
+                    let record: R = row.into();
+                    data.extend(encode(format, &record, first)?);
+                    first = false;
+                }
+                if tx.send(Ok(Bytes::from(data))).await.is_err() {
+                    // receiver is gone, e.g. the client closed the connection
+                    break;
+                }
+            }
+            eyre::Result::<()>::Ok(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            warn!("export failed: {:?}", err);
+            let _ = tx.send(Err(std::io::Error::other(err.to_string()))).await;
+        }
+    });
+    rx
+}
+
+fn export(db: Db, kind: ExportKind, query: &ExportQuery) -> mpsc::Receiver<std::io::Result<Bytes>> {
+    match kind {
+        ExportKind::Users => spawn_export::<_, UserRow>(
+            db,
+            user::Entity::find().order_by_asc(user::Column::Id),
+            query.format,
+        ),
+        ExportKind::Products => spawn_export::<_, ProductRow>(
+            db,
+            product::Entity::find().order_by_asc(product::Column::Id),
+            query.format,
+        ),
+        ExportKind::Transactions => spawn_export::<_, TransactionRow>(
+            db,
+            in_range(
+                transaction::Entity::find().order_by_asc(transaction::Column::Id),
+                transaction::Column::CreatedAt,
+                query.from,
+                query.to,
+            ),
+            query.format,
+        ),
+    }
+}
+
+fn respond(db: Db, kind: ExportKind, query: ExportQuery) -> impl IntoResponse {
+    let rx = export(db, kind, &query);
+    (
+        Headers([(CONTENT_TYPE, query.format.content_type())]),
+        StreamBody::new(ReceiverStream::new(rx)),
+    )
+}
+
+/// all users including their balance
+async fn users(
+    Query(query): Query<ExportQuery>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> impl IntoResponse {
+    respond(db, ExportKind::Users, query)
+}
+
+async fn products(
+    Query(query): Query<ExportQuery>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> impl IntoResponse {
+    respond(db, ExportKind::Products, query)
+}
+
+/// the transaction ledger, optionally restricted to a date range
+async fn transactions(
+    Query(query): Query<ExportQuery>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> impl IntoResponse {
+    respond(db, ExportKind::Transactions, query)
+}
+
+/// entry point of the `export` command, writes to stdout if no output is given
+pub async fn run(
+    db: &Db,
+    kind: ExportKind,
+    query: ExportQuery,
+    output: Option<PathBuf>,
+) -> eyre::Result<()> {
+    let mut writer: Box<dyn tokio::io::AsyncWrite + Unpin> = match output {
+        Some(path) => Box::new(
+            tokio::fs::File::create(&path)
+                .await
+                .wrap_err_with(|| eyre!("unable to create {}", path.display()))?,
+        ),
+        None => Box::new(tokio::io::stdout()),
+    };
+
+    let mut rx = export(db.clone(), kind, &query);
+    while let Some(data) = rx.recv().await {
+        writer.write_all(&data?).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn csv_header_only_in_front_of_first_record() {
+        let row = TransactionRow {
+            id: 1,
+            created_at: DateTime::from_utc(NaiveDate::from_ymd(2022, 4, 1).and_hms(12, 0, 0), Utc),
+            kind: transaction::Kind::Purchase,
+            user: None,
+            product: Some(2),
+            amount: -150,
+            reference: None,
+            group_account: Some(3),
+            event: None,
+        };
+
+        let first = String::from_utf8(encode(ExportFormat::Csv, &row, true).unwrap()).unwrap();
+        let second = String::from_utf8(encode(ExportFormat::Csv, &row, false).unwrap()).unwrap();
+        let jsonl = String::from_utf8(encode(ExportFormat::Jsonl, &row, true).unwrap()).unwrap();
+
+        assert_eq!(
+            first,
+            "id,created_at,kind,user,product,amount,reference,group_account,event\n\
+             1,2022-04-01T12:00:00Z,purchase,,2,-150,,3,\n"
+        );
+        assert_eq!(second, "1,2022-04-01T12:00:00Z,purchase,,2,-150,,3,\n");
+        assert_eq!(
+            jsonl,
+            "{\"id\":1,\"created_at\":\"2022-04-01T12:00:00Z\",\"kind\":\"purchase\",\
+             \"user\":null,\"product\":2,\"amount\":-150,\"reference\":null,\
+             \"group_account\":3,\"event\":null}\n"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use sea_orm::{entity::*, query::*, ConnectionTrait, TransactionTrait};
+
+use crate::{
+    auth::Admin,
+    entity::{
+        group_account, group_member, product,
+        transaction::{self, Kind},
+        user, webhook_event,
+    },
+    live::Live,
+    models::{
+        GroupAccount, GroupBuyRequest, GroupCreateRequest, GroupMember, GroupMemberRequest,
+        LedgerEntry, LiveEvent,
+    },
+    products,
+    storage::Db,
+    user::ensure_may_buy,
+    utils::{AppError, Result},
+    webhooks,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", routing::get(get_all).post(create))
+        .route("/:id", routing::get(get))
+        .route("/:id/members", routing::post(add_member))
+        .route("/:id/members/:user", routing::delete(remove_member))
+        .route("/:id/deposit", routing::post(deposit))
+        .route("/:id/buy", routing::post(buy))
+}
+
+/// sum of all purchases every member charged to the group
+fn spent_per_member(purchases: &[transaction::Model]) -> HashMap<i32, i32> {
+    let mut spent = HashMap::new();
+    for t in purchases {
+        if let Some(user) = t.user {
+            *spent.entry(user).or_insert(0) -= t.amount;
+        }
+    }
+    spent
+}
+
+fn ensure_below_cap(member: &group_member::Model, spent: i32, price: i32) -> Result<()> {
+    match member.cap {
+        Some(cap) if spent + price > cap => Err(AppError::CapExceeded),
+        _ => Ok(()),
+    }
+}
+
+async fn purchases(
+    db: &impl ConnectionTrait,
+    group: i32,
+    user: Option<i32>,
+) -> Result<Vec<transaction::Model>> {
+    let mut select = transaction::Entity::find()
+        .filter(transaction::Column::Kind.eq(Kind::Purchase))
+        .filter(transaction::Column::GroupAccount.eq(group));
+    if let Some(user) = user {
+        select = select.filter(transaction::Column::User.eq(user));
+    }
+    Ok(select.all(db).await?)
+}
+
+async fn find(db: &impl ConnectionTrait, id: i32) -> Result<group_account::Model> {
+    group_account::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFount)
+}
+
+async fn member(db: &impl ConnectionTrait, group: i32, user: i32) -> Result<group_member::Model> {
+    group_member::Entity::find()
+        .filter(group_member::Column::GroupAccount.eq(group))
+        .filter(group_member::Column::User.eq(user))
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFount)
+}
+
+/// the group including its members and what they spent
+async fn with_members(
+    db: &impl ConnectionTrait,
+    group: group_account::Model,
+) -> Result<GroupAccount> {
+    let spent = spent_per_member(&purchases(db, group.id, None).await?);
+    let members = group_member::Entity::find()
+        .filter(group_member::Column::GroupAccount.eq(group.id))
+        .order_by_asc(group_member::Column::User)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|m| GroupMember {
+            user: m.user,
+            cap: m.cap,
+            spent: spent.get(&m.user).copied().unwrap_or(0),
+        })
+        .collect();
+
+    Ok(GroupAccount {
+        id: group.id,
+        name: group.name,
+        balance: group.balance,
+        created_at: chrono::DateTime::from_utc(group.created_at, chrono::Utc),
+        updated_at: chrono::DateTime::from_utc(group.updated_at, chrono::Utc),
+        members,
+    })
+}
+
+async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<GroupAccount>>> {
+    let mut groups = Vec::new();
+    for group in group_account::Entity::find().all(&db.orm).await? {
+        groups.push(with_members(&db.orm, group).await?);
+    }
+    Ok(Json(groups))
+}
+
+async fn create(
+    Json(request): Json<GroupCreateRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<(StatusCode, Json<GroupAccount>)> {
+    let group = group_account::ActiveModel {
+        name: Set(request.name),
+        balance: Set(0),
+        ..Default::default()
+    }
+    .insert(&db.orm)
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(with_members(&db.orm, group).await?),
+    ))
+}
+
+async fn get(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<Json<GroupAccount>> {
+    let group = find(&db.orm, id).await?;
+    Ok(Json(with_members(&db.orm, group).await?))
+}
+
+/// adds a user to the group or changes the cap of a member, members must not
+/// be able to lift their own cap
+async fn add_member(
+    Path(id): Path<i32>,
+    Json(request): Json<GroupMemberRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<Json<GroupAccount>> {
+    let group = db
+        .orm
+        .transaction::<_, GroupAccount, AppError>(|txn| {
+            Box::pin(async move {
+                let group = find(txn, id).await?;
+                user::Entity::find_by_id(request.user)
+                    .one(txn)
+                    .await?
+                    .ok_or(AppError::NotFount)?;
+
+                match member(txn, id, request.user).await {
+                    Ok(member) => {
+                        let mut member = member.into_active_model();
+                        member.cap = Set(request.cap);
+                        member.update(txn).await?;
+                    }
+                    Err(AppError::NotFount) => {
+                        group_member::ActiveModel {
+                            group_account: Set(id),
+                            user: Set(request.user),
+                            cap: Set(request.cap),
+                            ..Default::default()
+                        }
+                        .insert(txn)
+                        .await?;
+                    }
+                    Err(err) => return Err(err),
+                }
+
+                with_members(txn, group).await
+            })
+        })
+        .await?;
+
+    Ok(Json(group))
+}
+
+async fn remove_member(
+    Path((id, user)): Path<(i32, i32)>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<Json<GroupAccount>> {
+    let group = find(&db.orm, id).await?;
+    member(&db.orm, id, user)
+        .await?
+        .into_active_model()
+        .delete(&db.orm)
+        .await?;
+    Ok(Json(with_members(&db.orm, group).await?))
+}
+
+/// cash paid into the group account
+async fn deposit(
+    Path(id): Path<i32>,
+    body: String,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+) -> Result<Json<GroupAccount>> {
+    let amount = body.parse::<i32>()?;
+    let (group, deposit) = db
+        .orm
+        .transaction::<_, _, AppError>(|txn| {
+            Box::pin(async move {
+                let group = find(txn, id).await?;
+                let balance = group.balance;
+                let mut group = group.into_active_model();
+                group.balance = Set(balance + amount);
+                let group = group.update(txn).await?;
+
+                let deposit = transaction::ActiveModel {
+                    kind: Set(Kind::Deposit),
+                    amount: Set(amount),
+                    group_account: Set(Some(id)),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+                let data = LedgerEntry::from(deposit.clone());
+                webhooks::emit(txn, webhook_event::Kind::Deposit, &data).await?;
+
+                Ok((with_members(txn, group).await?, deposit))
+            })
+        })
+        .await?;
+
+    live.publish([LiveEvent::Transaction(deposit.into())]);
+    Ok(Json(group))
+}
+
+/// a member buys a product on the tab of the group
+async fn buy(
+    Path(id): Path<i32>,
+    Json(request): Json<GroupBuyRequest>,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+) -> Result<Json<GroupAccount>> {
+    let (group, product, purchase) = db
+        .orm
+        .transaction::<_, _, AppError>(|txn| {
+            Box::pin(async move {
+                let group = find(txn, id).await?;
+                let member = member(txn, id, request.user).await?;
+                let user = user::Entity::find_by_id(request.user)
+                    .one(txn)
+                    .await?
+                    .ok_or(AppError::NotFount)?;
+                let product = product::Entity::find_by_id(request.product)
+                    .one(txn)
+                    .await?
+                    .ok_or(AppError::NotFount)?;
+                ensure_may_buy(txn, &user, &product).await?;
+
+                let spent = spent_per_member(&purchases(txn, id, Some(user.id)).await?);
+                ensure_below_cap(
+                    &member,
+                    spent.get(&user.id).copied().unwrap_or(0),
+                    product.price,
+                )?;
+
+                let balance = group.balance;
+                let mut group = group.into_active_model();
+                group.balance = Set(balance - product.price);
+                let group = group.update(txn).await?;
+
+                let purchase = transaction::ActiveModel {
+                    kind: Set(Kind::Purchase),
+                    user: Set(Some(user.id)),
+                    product: Set(Some(product.id)),
+                    amount: Set(-product.price),
+                    event: Set(user.event),
+                    group_account: Set(Some(id)),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+                let data = LedgerEntry::from(purchase.clone());
+                webhooks::emit(txn, webhook_event::Kind::Purchase, &data).await?;
+                let product = products::take_from_stock(txn, product, 1).await?;
+
+                Ok((with_members(txn, group).await?, product, purchase))
+            })
+        })
+        .await?;
+
+    live.publish([
+        LiveEvent::Product(product.into()),
+        LiveEvent::Transaction(purchase.into()),
+    ]);
+    Ok(Json(group))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        repository::Repositories,
+        testing::{self, send, send_admin},
+    };
+
+    #[test]
+    fn caps_limit_the_sum_of_purchases() {
+        let now = Utc::now().naive_utc();
+        let purchase = |user, amount| transaction::Model {
+            id: 0,
+            kind: Kind::Purchase,
+            user: Some(user),
+            product: Some(1),
+            amount,
+            created_at: now,
+            reference: None,
+            event: None,
+            group_account: Some(1),
+        };
+        let spent = spent_per_member(&[purchase(1, -150), purchase(2, -200), purchase(1, -150)]);
+        let member = |cap| group_member::Model {
+            id: 1,
+            group_account: 1,
+            user: 1,
+            cap,
+            created_at: now,
+        };
+
+        assert_eq!(spent[&1], 300);
+        assert_eq!(spent[&2], 200);
+        assert!(ensure_below_cap(&member(Some(450)), spent[&1], 150).is_ok());
+        assert!(matches!(
+            ensure_below_cap(&member(Some(400)), spent[&1], 150),
+            Err(AppError::CapExceeded)
+        ));
+        assert!(ensure_below_cap(&member(None), spent[&1], 150).is_ok());
+    }
+
+    #[tokio::test]
+    async fn only_admins_manage_groups_and_caps() {
+        for db in testing::backends("groups").await {
+            let alice = crate::models::UserCreateRequest {
+                name: "alice".to_string(),
+                ..Default::default()
+            };
+            let alice = crate::user::insert(&db.orm, alice, None, None)
+                .await
+                .unwrap();
+            let app = testing::app(router(), Repositories::database(db.clone()), Some(db));
+
+            let crew = r#"{"name": "crew"}"#;
+            let (status, _) = send(&app, "POST", "/", crew).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            let (status, group) = send_admin(&app, "POST", "/", crew).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let members = format!("/{}/members", group["id"]);
+
+            let capped = json!({"user": alice.id, "cap": 500}).to_string();
+            let (status, _) = send_admin(&app, "POST", &members, capped).await;
+            assert_eq!(status, StatusCode::OK);
+            let uncapped = json!({"user": alice.id}).to_string();
+            let (status, _) = send(&app, "POST", &members, uncapped).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            let member = format!("{}/{}", members, alice.id);
+            let (status, _) = send(&app, "DELETE", &member, "").await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+            let (_, group) = send(&app, "GET", &format!("/{}", group["id"]), "").await;
+            assert_eq!(group["members"][0]["cap"], json!(500));
+        }
+    }
+}
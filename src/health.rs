@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use axum::{extract::Extension, http::StatusCode, Json};
+use sea_orm::{ConnectionTrait, Statement};
+use tokio::signal;
+use tracing::{info, warn};
+
+use crate::{
+    models::Readiness,
+    storage::{self, Db},
+};
+
+/// time in-flight requests get to complete once the server is asked to stop
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// liveness probe, answers as long as the server is running
+pub(crate) async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// readiness probe, fails unless the database can be queried and is migrated
+pub(crate) async fn readyz(Extension(db): Extension<Db>) -> (StatusCode, Json<Readiness>) {
+    let ping = Statement::from_string(db.orm.get_database_backend(), "SELECT 1".to_owned());
+    let readiness = match db.orm.execute(ping).await {
+        Ok(_) => match storage::pending_migrations(&db).await {
+            Ok(pending_migrations) => Readiness {
+                database: true,
+                pending_migrations,
+            },
+            Err(err) => {
+                warn!("unable to list applied migrations: {:?}", err);
+                Readiness {
+                    database: false,
+                    pending_migrations: Vec::new(),
+                }
+            }
+        },
+        Err(err) => {
+            warn!("database is not reachable: {:?}", err);
+            Readiness {
+                database: false,
+                pending_migrations: Vec::new(),
+            }
+        }
+    };
+
+    let status = if readiness.database && readiness.pending_migrations.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+/// resolves once the process receives SIGTERM or ctrl-c
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            warn!("unable to listen for ctrl-c: {:?}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                warn!("unable to listen for SIGTERM: {:?}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutting down, waiting for running requests");
+}
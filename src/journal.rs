@@ -0,0 +1,490 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::PathBuf,
+};
+
+use axum::{
+    extract::{Extension, Query},
+    http::header::CONTENT_TYPE,
+    response::{Headers, IntoResponse},
+};
+use chrono::NaiveDate;
+use eyre::{eyre, Context};
+use sea_orm::{entity::*, query::*, ConnectionTrait};
+use serde::Deserialize;
+
+use crate::{
+    auth::Admin,
+    config::{Config, JournalConfig},
+    entity::{
+        bank_transaction, group_account, product,
+        transaction::{self, Kind},
+        user,
+    },
+    stats::{in_range, to_local},
+    storage::Db,
+    utils::Result,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalFormat {
+    #[default]
+    Beancount,
+    /// ledger-cli and hledger
+    Ledger,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JournalQuery {
+    #[serde(default)]
+    pub format: JournalFormat,
+    /// first day (inclusive, local time) of exported transactions
+    pub from: Option<NaiveDate>,
+    /// last day (inclusive, local time) of exported transactions
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    date: NaiveDate,
+    payee: Option<String>,
+    narration: String,
+    /// account and amount in cent, the amounts of an entry sum up to zero
+    postings: Vec<(String, i32)>,
+}
+
+/// Turns a name into a single component of an account name. Beancount only
+/// allows letters, digits and dashes and requires a capital letter or digit
+/// at the start.
+fn account_component(name: &str) -> String {
+    let cleaned = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let mut chars = cleaned.chars();
+    match chars.next() {
+        Some(first) if first.is_lowercase() => first.to_uppercase().chain(chars).collect(),
+        Some(first) if first.is_uppercase() || first.is_ascii_digit() => cleaned,
+        Some(_) => format!("X{}", cleaned),
+        None => "Unnamed".to_owned(),
+    }
+}
+
+/// liability account of every user, users whose names end up with the same
+/// account get their id appended
+fn member_accounts(users: &[user::Model], config: &JournalConfig) -> HashMap<i32, String> {
+    let mut taken = HashSet::new();
+    users
+        .iter()
+        .map(|user| {
+            let mut account = format!("{}:{}", config.members, account_component(&user.name));
+            if !taken.insert(account.clone()) {
+                account = format!("{}-{}", account, user.id);
+            }
+            (user.id, account)
+        })
+        .collect()
+}
+
+/// Starting balances of imported users and balances taken over from mete
+/// were never paid into the cashbox.
+fn is_opening_balance(transaction: &transaction::Model) -> bool {
+    match transaction.reference.as_deref() {
+        Some(reference) => reference == "import" || reference.starts_with("mete audit "),
+        None => false,
+    }
+}
+
+struct Ledger<'a> {
+    config: &'a JournalConfig,
+    users: HashMap<i32, String>,
+    members: HashMap<i32, String>,
+    groups: HashMap<i32, String>,
+    products: HashMap<i32, String>,
+    /// deposits booked from bank statements
+    bank_deposits: HashSet<i32>,
+}
+
+impl Ledger<'_> {
+    fn member(&self, user: i32) -> String {
+        self.members
+            .get(&user)
+            .cloned()
+            .unwrap_or_else(|| format!("{}:User{}", self.config.members, user))
+    }
+
+    fn group(&self, group: i32) -> String {
+        self.groups
+            .get(&group)
+            .cloned()
+            .unwrap_or_else(|| format!("{}:Group{}", self.config.groups, group))
+    }
+
+    fn user_name(&self, user: Option<i32>) -> Option<String> {
+        user.map(|id| {
+            self.users
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(|| format!("user {}", id))
+        })
+    }
+
+    fn revenue(&self, product: Option<i32>) -> String {
+        product
+            .and_then(|id| self.config.products.get(&id))
+            .unwrap_or(&self.config.revenue)
+            .clone()
+    }
+
+    /// Books every transaction against the account of the group or user, or the
+    /// cashbox for anonymous sales. Both halves of a transfer are combined into one
+    /// entry, if only one of them is exported it is booked against the
+    /// clearing account. Merges are skipped, the history of the merged user
+    /// was moved to the remaining one, so the moved balance is already part of
+    /// its account.
+    fn entries(&self, transactions: &[transaction::Model]) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut transactions = transactions.iter().peekable();
+
+        while let Some(t) = transactions.next() {
+            let date = to_local(t.created_at).date();
+            let source = match (t.group_account, t.user) {
+                (Some(group), _) => self.group(group),
+                (None, Some(user)) => self.member(user),
+                (None, None) => self.config.cashbox.clone(),
+            };
+
+            if t.kind == Kind::Transfer {
+                let receiver = transactions.next_if(|r| {
+                    r.kind == Kind::Transfer
+                        && r.user.is_some()
+                        && t.amount < 0
+                        && r.amount == -t.amount
+                });
+                if let Some(receiver) = receiver.and_then(|r| r.user) {
+                    entries.push(Entry {
+                        date,
+                        payee: self.user_name(t.user),
+                        narration: format!(
+                            "transfer to {}",
+                            self.user_name(Some(receiver)).unwrap_or_default()
+                        ),
+                        postings: vec![(source, -t.amount), (self.member(receiver), t.amount)],
+                    });
+                    continue;
+                }
+            }
+
+            let (counter, narration) = match t.kind {
+                Kind::Purchase => (
+                    self.revenue(t.product),
+                    t.product
+                        .and_then(|id| self.products.get(&id).cloned())
+                        .unwrap_or_else(|| "purchase".to_owned()),
+                ),
+                Kind::Deposit => (
+                    if self.bank_deposits.contains(&t.id) {
+                        self.config.bank.clone()
+                    } else if is_opening_balance(t) {
+                        self.config.opening.clone()
+                    } else {
+                        self.config.cashbox.clone()
+                    },
+                    t.reference.clone().unwrap_or_else(|| "deposit".to_owned()),
+                ),
+                Kind::Spend => (
+                    self.config.revenue.clone(),
+                    t.reference.clone().unwrap_or_else(|| "spend".to_owned()),
+                ),
+                Kind::Transfer => (self.config.transfers.clone(), "transfer".to_owned()),
+                Kind::Merge => continue,
+            };
+            entries.push(Entry {
+                date,
+                payee: self.user_name(t.user),
+                narration,
+                postings: vec![(source, -t.amount), (counter, t.amount)],
+            });
+        }
+
+        entries
+    }
+}
+
+pub(crate) fn amount(cent: i32) -> String {
+    let sign = if cent < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, cent.abs() / 100, cent.abs() % 100)
+}
+
+fn single_line(text: &str) -> String {
+    text.replace(['\n', '\r'], " ")
+}
+
+fn quoted(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        single_line(text).replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+fn render(format: JournalFormat, entries: &[Entry], config: &JournalConfig) -> String {
+    let mut accounts = entries
+        .iter()
+        .flat_map(|e| e.postings.iter().map(|(account, _)| account.as_str()))
+        .collect::<Vec<_>>();
+    accounts.sort_unstable();
+    accounts.dedup();
+
+    let mut journal = String::new();
+    // beancount requires every account to be opened before its first use
+    let opened = entries.iter().map(|e| e.date).min();
+    for account in accounts {
+        let _ = match (format, opened) {
+            (JournalFormat::Beancount, Some(opened)) => {
+                writeln!(journal, "{} open {}", opened, account)
+            }
+            (JournalFormat::Beancount, None) => Ok(()),
+            (JournalFormat::Ledger, _) => writeln!(journal, "account {}", account),
+        };
+    }
+
+    for entry in entries {
+        let _ = match format {
+            JournalFormat::Beancount => match &entry.payee {
+                Some(payee) => writeln!(
+                    journal,
+                    "\n{} * {} {}",
+                    entry.date,
+                    quoted(payee),
+                    quoted(&entry.narration)
+                ),
+                None => writeln!(journal, "\n{} * {}", entry.date, quoted(&entry.narration)),
+            },
+            JournalFormat::Ledger => match &entry.payee {
+                Some(payee) => writeln!(
+                    journal,
+                    "\n{} * {} | {}",
+                    entry.date,
+                    single_line(payee),
+                    single_line(&entry.narration)
+                ),
+                None => writeln!(
+                    journal,
+                    "\n{} * {}",
+                    entry.date,
+                    single_line(&entry.narration)
+                ),
+            },
+        };
+        for (account, cent) in &entry.postings {
+            let _ = writeln!(
+                journal,
+                "  {:<40} {:>10} {}",
+                account,
+                amount(*cent),
+                config.currency
+            );
+        }
+    }
+
+    journal
+}
+
+async fn journal(
+    db: &impl ConnectionTrait,
+    config: &JournalConfig,
+    query: &JournalQuery,
+) -> Result<String> {
+    let users = user::Entity::find()
+        .order_by_asc(user::Column::Id)
+        .all(db)
+        .await?;
+    let ledger = Ledger {
+        config,
+        members: member_accounts(&users, config),
+        users: users.into_iter().map(|u| (u.id, u.name)).collect(),
+        groups: group_account::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|g| {
+                (
+                    g.id,
+                    format!("{}:{}", config.groups, account_component(&g.name)),
+                )
+            })
+            .collect(),
+        products: product::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|p| (p.id, p.name))
+            .collect(),
+        bank_deposits: bank_transaction::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .filter_map(|b| b.transaction)
+            .collect(),
+    };
+
+    let transactions = in_range(
+        transaction::Entity::find().order_by_asc(transaction::Column::Id),
+        transaction::Column::CreatedAt,
+        query.from,
+        query.to,
+    )
+    .all(db)
+    .await?;
+
+    Ok(render(query.format, &ledger.entries(&transactions), config))
+}
+
+/// the transaction ledger as plain text accounting journal
+pub(crate) async fn get(
+    Query(query): Query<JournalQuery>,
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<Config>,
+    _: Admin,
+) -> Result<impl IntoResponse> {
+    let journal = journal(&db.orm, &config.journal, &query).await?;
+    Ok((
+        Headers([(CONTENT_TYPE, "text/plain; charset=utf-8")]),
+        journal,
+    ))
+}
+
+/// entry point of the `journal` command, writes to stdout if no output is given
+pub async fn run(
+    db: &Db,
+    config: &JournalConfig,
+    query: JournalQuery,
+    output: Option<PathBuf>,
+) -> eyre::Result<()> {
+    let journal = journal(&db.orm, config, &query)
+        .await
+        .map_err(|err| eyre!("{:?}", err))?;
+    match output {
+        Some(path) => tokio::fs::write(&path, journal)
+            .await
+            .wrap_err_with(|| eyre!("unable to write {}", path.display()))?,
+        None => print!("{}", journal),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn transaction(
+        id: i32,
+        kind: Kind,
+        user: Option<i32>,
+        product: Option<i32>,
+        amount: i32,
+    ) -> transaction::Model {
+        transaction::Model {
+            id,
+            kind,
+            user,
+            product,
+            amount,
+            // noon utc is on the same day in every european time zone
+            created_at: NaiveDateTime::parse_from_str("2022-04-01 12:00", "%Y-%m-%d %H:%M")
+                .unwrap(),
+            reference: None,
+            event: None,
+            group_account: None,
+        }
+    }
+
+    #[test]
+    fn account_components_are_valid_beancount() {
+        assert_eq!(account_component("alice"), "Alice");
+        assert_eq!(account_component("Bob the builder"), "Bob-the-builder");
+        assert_eq!(account_component("_x.y_"), "X-y");
+        assert_eq!(account_component("1337"), "1337");
+        assert_eq!(account_component("..."), "Unnamed");
+    }
+
+    #[test]
+    fn renders_balanced_beancount_entries() {
+        let mut config = JournalConfig::default();
+        config
+            .products
+            .insert(2, "Income:Drinks:Alcohol".to_owned());
+        let ledger = Ledger {
+            config: &config,
+            users: HashMap::from([(1, "alice".to_owned()), (2, "bob".to_owned())]),
+            members: HashMap::from([
+                (1, "Liabilities:Members:Alice".to_owned()),
+                (2, "Liabilities:Members:Bob".to_owned()),
+            ]),
+            groups: HashMap::new(),
+            products: HashMap::from([(1, "Club-Mate".to_owned()), (2, "Beer".to_owned())]),
+            bank_deposits: HashSet::from([2]),
+        };
+        let transactions = [
+            transaction(1, Kind::Deposit, Some(1), None, 1000),
+            transaction(2, Kind::Deposit, Some(2), None, 500),
+            transaction(3, Kind::Purchase, Some(1), Some(1), -150),
+            transaction(4, Kind::Purchase, None, Some(2), -200),
+            transaction(5, Kind::Transfer, Some(1), None, -300),
+            transaction(6, Kind::Transfer, Some(2), None, 300),
+            transaction(7, Kind::Merge, Some(1), None, 200),
+            transaction::Model {
+                reference: Some("import".to_owned()),
+                ..transaction(8, Kind::Deposit, Some(1), None, 700)
+            },
+            transaction::Model {
+                reference: Some("mete audit 3".to_owned()),
+                ..transaction(9, Kind::Deposit, Some(2), None, -100)
+            },
+        ];
+
+        let entries = ledger.entries(&transactions);
+
+        assert_eq!(entries.len(), 7);
+        assert!(entries
+            .iter()
+            .all(|e| e.postings.iter().map(|(_, cent)| cent).sum::<i32>() == 0));
+        assert_eq!(
+            render(JournalFormat::Beancount, &entries[2..5], &config),
+            "\
+2022-04-01 open Assets:Cashbox
+2022-04-01 open Income:Drinks
+2022-04-01 open Income:Drinks:Alcohol
+2022-04-01 open Liabilities:Members:Alice
+2022-04-01 open Liabilities:Members:Bob
+
+2022-04-01 * \"alice\" \"Club-Mate\"
+  Liabilities:Members:Alice                      1.50 EUR
+  Income:Drinks                                 -1.50 EUR
+
+2022-04-01 * \"Beer\"
+  Assets:Cashbox                                 2.00 EUR
+  Income:Drinks:Alcohol                         -2.00 EUR
+
+2022-04-01 * \"alice\" \"transfer to bob\"
+  Liabilities:Members:Alice                      3.00 EUR
+  Liabilities:Members:Bob                       -3.00 EUR
+"
+        );
+        let counters = entries
+            .iter()
+            .map(|e| e.postings[1].0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(counters[..2], ["Assets:Cashbox", "Assets:Bank"]);
+        assert_eq!(
+            counters[5..],
+            ["Equity:Opening-Balances", "Equity:Opening-Balances"]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::Extension,
+    response::sse::{Event, KeepAlive, Sse},
+    routing, Router,
+};
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+use crate::models::LiveEvent;
+
+/// events buffered for slow clients before they miss some
+const CAPACITY: usize = 256;
+
+pub fn router() -> Router {
+    Router::new().route("/", routing::get(stream))
+}
+
+/// Broadcasts changes to all connected clients, every handler changing users,
+/// products or the ledger publishes to it after its transaction was committed.
+#[derive(Debug, Clone)]
+pub struct Live {
+    sender: broadcast::Sender<LiveEvent>,
+}
+
+impl Default for Live {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Live {
+    pub(crate) fn publish(&self, events: impl IntoIterator<Item = LiveEvent>) {
+        for event in events {
+            // fails only if nobody is listening
+            let _ = self.sender.send(event);
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl LiveEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            LiveEvent::User(_) => "user",
+            LiveEvent::UserDeleted(_) => "user_deleted",
+            LiveEvent::Product(_) => "product",
+            LiveEvent::ProductDeleted(_) => "product_deleted",
+            LiveEvent::Transaction(_) => "transaction",
+        }
+    }
+}
+
+fn to_sse(message: Result<LiveEvent, BroadcastStreamRecvError>) -> Option<Event> {
+    match message {
+        Ok(event) => Event::default().event(event.name()).json_data(&event).ok(),
+        // the client has to reload everything it shows
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            Some(Event::default().event("lagged").data(missed.to_string()))
+        }
+    }
+}
+
+/// server-sent events of every change to users, products and the ledger
+async fn stream(
+    Extension(live): Extension<Live>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = BroadcastStream::new(live.subscribe())
+        .filter_map(|message| async move { to_sse(message).map(Ok) });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_named_by_their_type() {
+        let event = LiveEvent::UserDeleted(3);
+
+        assert_eq!(event.name(), "user_deleted");
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"user_deleted","data":3}"#
+        );
+        assert!(to_sse(Err(BroadcastStreamRecvError::Lagged(5))).is_some());
+    }
+}
@@ -1,39 +1,423 @@
-use axum::{extract::Extension, Router};
+use std::path::PathBuf;
+
+use axum::{extract::Extension, middleware, routing, Router};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use eyre::Result;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 
+mod auth;
+mod backup;
+mod bank;
+mod cashbox;
+mod cleanup;
 mod config;
 mod entity;
+mod events;
+mod export;
+mod groups;
+mod health;
+mod journal;
+mod live;
+mod mete;
+mod metrics;
 mod models;
+mod mqtt;
+mod notifications;
+mod nutrition;
 mod products;
+mod recurring;
+mod repository;
 mod server;
+mod stats;
 mod storage;
+#[cfg(test)]
+mod testing;
 mod user;
 mod utils;
+mod webhooks;
+
+#[derive(Debug, Parser)]
+#[clap(version, about)]
+struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the http server, this is the default
+    Serve,
+    /// Import users, drinks, barcodes and audits of a Mete installation
+    ImportMete {
+        /// sqlite database of mete or directory containing the json exports
+        /// of its API
+        #[clap(value_parser)]
+        source: PathBuf,
+        /// only report what would be imported
+        #[clap(long, action)]
+        dry_run: bool,
+    },
+    /// Create users from a csv file with the columns name, email, balance,
+    /// barcode and audit
+    ImportUsers {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Export users, products or the transaction ledger
+    Export {
+        #[clap(value_enum)]
+        kind: export::ExportKind,
+        #[clap(long, value_enum, default_value_t)]
+        format: export::ExportFormat,
+        /// first day of exported transactions, e.g. 2022-01-01
+        #[clap(long, value_parser)]
+        from: Option<NaiveDate>,
+        /// last day of exported transactions
+        #[clap(long, value_parser)]
+        to: Option<NaiveDate>,
+        /// file to write to instead of stdout
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Export the transaction ledger as plain text accounting journal
+    Journal {
+        #[clap(long, value_enum, default_value_t)]
+        format: journal::JournalFormat,
+        /// first day of exported transactions, e.g. 2022-01-01
+        #[clap(long, value_parser)]
+        from: Option<NaiveDate>,
+        /// last day of exported transactions
+        #[clap(long, value_parser)]
+        to: Option<NaiveDate>,
+        /// file to write to instead of stdout
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Write a verified copy of the database into the backup directory
+    Backup {
+        /// file to write to instead of the backup directory
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Replace the database by a backup, the server must be stopped
+    Restore {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    let args = Args::parse();
     let config = config::load_config().await.expect("unable to load config");
+    let command = args.command.unwrap_or(Command::Serve);
 
-    let db = storage::open_db(config.storage.database.clone()).await?;
+    // the database must not be open while it is replaced
+    if let Command::Restore { file } = command {
+        return backup::restore(&config, file).await;
+    }
 
-    let api_routes = Router::new()
-        .nest("/info", server::router())
-        .nest("/users", user::router())
-        .nest("/products", products::router());
+    let db = storage::open_db(&config.storage).await?;
+    storage::migrate(&db).await?;
 
-    let app = Router::new()
-        .nest("/api/v3", api_routes)
-        .layer(Extension(config.clone()))
-        .layer(Extension(db.clone()))
-        .layer(TraceLayer::new_for_http());
+    match command {
+        Command::Serve => serve(config, db).await,
+        Command::ImportMete { source, dry_run } => mete::run(&db, source, dry_run).await,
+        Command::ImportUsers { file } => user::run_import(&db, file).await,
+        Command::Export {
+            kind,
+            format,
+            from,
+            to,
+            output,
+        } => export::run(&db, kind, export::ExportQuery { format, from, to }, output).await,
+        Command::Journal {
+            format,
+            from,
+            to,
+            output,
+        } => {
+            let query = journal::JournalQuery { format, from, to };
+            journal::run(&db, &config.journal, query, output).await
+        }
+        Command::Backup { output } => backup::run(&db, &config.backup, output).await,
+        Command::Restore { .. } => unreachable!("restored before opening the database"),
+    }
+}
+
+async fn serve(config: config::Config, mut db: storage::Db) -> Result<()> {
+    let metrics = metrics::Metrics::new()?;
+    let query_metrics = metrics.clone();
+    db.orm
+        .set_metric_callback(move |info| query_metrics.observe_query(info));
+
+    let live = live::Live::default();
+    let app = app(&config, &db, &live, metrics);
+
+    tokio::spawn(recurring::scheduler(db.clone(), live.clone()));
+    tokio::spawn(cleanup::scheduler(db.clone(), config.cleanup.clone()));
+    tokio::spawn(webhooks::worker(db.clone(), config.webhooks.clone()));
+    tokio::spawn(notifications::scheduler(
+        db.clone(),
+        config.notifications.clone(),
+    ));
+    tokio::spawn(backup::scheduler(db.clone(), config.backup.clone()));
+    tokio::spawn(mqtt::publisher(db.clone(), config.mqtt.clone(), live));
+
+    let (stop, stopping) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        health::shutdown_signal().await;
+        let _ = stop.send(true);
+    });
+    let mut graceful = stopping.clone();
+    let mut forced = stopping;
 
     info!("listening on {}", config.http.listen);
-    axum::Server::bind(&config.http.listen)
+    let server = axum::Server::bind(&config.http.listen)
         .serve(app.into_make_service())
-        .await?;
+        .with_graceful_shutdown(async move {
+            let _ = graceful.changed().await;
+        });
+    // open live streams never finish on their own
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            let _ = forced.changed().await;
+            tokio::time::sleep(health::SHUTDOWN_TIMEOUT).await;
+        } => warn!("requests still running after {:?}, stopping anyway", health::SHUTDOWN_TIMEOUT),
+    }
 
     Ok(())
 }
+
+/// all routes of the server with everything the handlers need
+fn app(
+    config: &config::Config,
+    db: &storage::Db,
+    live: &live::Live,
+    metrics: metrics::Metrics,
+) -> Router {
+    // the full path of a route is only known within the nested router
+    let api_routes = [
+        ("/info", server::router()),
+        ("/users", user::router()),
+        ("/groups", groups::router()),
+        ("/products", products::router()),
+        ("/stats", stats::router()),
+        ("/cashbox", cashbox::router()),
+        ("/bank", bank::router()),
+        ("/events", events::router()),
+        ("/live", live::router()),
+        ("/recurring", recurring::router()),
+        ("/cleanup", cleanup::router()),
+        ("/notifications", notifications::router()),
+        ("/export", export::router()),
+        ("/backups", backup::router()),
+    ]
+    .into_iter()
+    .fold(Router::new(), |api, (path, router)| {
+        api.nest(
+            path,
+            router.route_layer(middleware::from_fn(metrics::track)),
+        )
+    });
+    let api_routes = repository::Repositories::database(db.clone()).layer(api_routes);
+
+    Router::new()
+        .route("/metrics", routing::get(metrics::export))
+        .route("/healthz", routing::get(health::healthz))
+        .route("/readyz", routing::get(health::readyz))
+        .route_layer(middleware::from_fn(metrics::track))
+        .nest("/api/v3", api_routes)
+        .layer(Extension(config.clone()))
+        .layer(Extension(db.clone()))
+        .layer(Extension(live.clone()))
+        .layer(Extension(metrics))
+        .layer(TraceLayer::new_for_http())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use pretty_assertions::assert_eq;
+    use sea_orm::EntityTrait;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::{self, send, send_admin};
+
+    /// Walks through every part of the api touching the database, so queries
+    /// that only work in one sql dialect are noticed.
+    #[tokio::test]
+    async fn api_works_on_every_backend() {
+        for db in testing::backends("api").await {
+            let metrics = metrics::Metrics::new().unwrap();
+            let app = app(&testing::config(), &db, &live::Live::default(), metrics);
+            let mut failed = Vec::new();
+            let mut check = |(status, body): (StatusCode, Value), request: &str| {
+                if !status.is_success() {
+                    failed.push(format!("{} -> {} {}", request, status, body));
+                }
+                body
+            };
+            macro_rules! call {
+                ($send:ident, $method:expr, $uri:expr, $body:expr) => {{
+                    let uri: String = $uri.into();
+                    let response = $send(&app, $method, &uri, $body).await;
+                    check(response, &format!("{} {}", $method, uri))
+                }};
+            }
+
+            let alice = call!(send, "POST", "/api/v3/users", r#"{"name": "alice"}"#)["id"].clone();
+            let bob = call!(send, "POST", "/api/v3/users", r#"{"name": "bob"}"#)["id"].clone();
+            let carol = call!(send, "POST", "/api/v3/users", r#"{"name": "carol"}"#)["id"].clone();
+            let body = r#"{"name": "Club-Mate", "volume": 500, "caffeine": 20, "stock": 10}"#;
+            let mate = call!(send, "POST", "/api/v3/products", body)["id"].clone();
+            call!(
+                send,
+                "POST",
+                format!("/api/v3/users/{}/deposit", alice),
+                "1000"
+            );
+            call!(
+                send,
+                "POST",
+                format!("/api/v3/users/{}/buy", alice),
+                mate.to_string()
+            );
+            call!(
+                send,
+                "POST",
+                format!("/api/v3/users/{}/deposit", carol),
+                "200"
+            );
+            call!(send, "POST", format!("/api/v3/products/{}/sell", mate), "2");
+
+            let group =
+                call!(send_admin, "POST", "/api/v3/groups", r#"{"name": "crew"}"#)["id"].clone();
+            let member = json!({"user": alice, "cap": 500}).to_string();
+            call!(
+                send_admin,
+                "POST",
+                format!("/api/v3/groups/{}/members", group),
+                member
+            );
+            call!(
+                send,
+                "POST",
+                format!("/api/v3/groups/{}/deposit", group),
+                "1000"
+            );
+            let buy = json!({"user": alice, "product": mate}).to_string();
+            call!(send, "POST", format!("/api/v3/groups/{}/buy", group), buy);
+            call!(send, "GET", "/api/v3/groups", "");
+
+            let event =
+                call!(send_admin, "POST", "/api/v3/events", r#"{"name": "camp"}"#)["id"].clone();
+            let guest = call!(
+                send,
+                "POST",
+                format!("/api/v3/events/{}/users", event),
+                r#"{"name": "guest"}"#
+            )["id"]
+                .clone();
+            call!(
+                send,
+                "POST",
+                format!("/api/v3/users/{}/buy", guest),
+                mate.to_string()
+            );
+            call!(
+                send,
+                "GET",
+                format!("/api/v3/events/{}/settlement", event),
+                ""
+            );
+            call!(
+                send_admin,
+                "POST",
+                format!("/api/v3/events/{}/close", event),
+                ""
+            );
+            let buy = format!("/api/v3/users/{}/buy", guest);
+            let (status, _) = send(&app, "POST", &buy, mate.to_string()).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+
+            call!(
+                send_admin,
+                "POST",
+                "/api/v3/cashbox/count",
+                r#"{"amount": 500}"#
+            );
+            call!(
+                send_admin,
+                "POST",
+                "/api/v3/cashbox/withdraw",
+                r#"{"amount": 200}"#
+            );
+            call!(send, "GET", "/api/v3/cashbox", "");
+            call!(send, "GET", "/api/v3/cashbox/report", "");
+
+            let charge =
+                json!({"name": "fee", "amount": 500, "interval": "monthly", "users": [bob]});
+            call!(send_admin, "POST", "/api/v3/recurring", charge.to_string());
+            call!(send_admin, "POST", "/api/v3/recurring/run", "");
+            call!(send, "GET", "/api/v3/recurring", "");
+
+            let merge = json!({"source": carol}).to_string();
+            let target = call!(
+                send_admin,
+                "POST",
+                format!("/api/v3/users/{}/merge", bob),
+                merge
+            );
+            assert_eq!(target["balance"], json!(-300));
+
+            for stats in [
+                "products", "hours", "weekdays", "revenue", "deposits", "top",
+            ] {
+                call!(send, "GET", format!("/api/v3/stats/{}", stats), "");
+            }
+            call!(
+                send,
+                "GET",
+                format!("/api/v3/users/{}/nutrition", alice),
+                ""
+            );
+            for export in ["users", "products", "transactions?format=jsonl", "journal"] {
+                call!(send_admin, "GET", format!("/api/v3/export/{}", export), "");
+            }
+            call!(send_admin, "GET", "/api/v3/cleanup?days=1", "");
+            call!(send_admin, "GET", "/api/v3/bank", "");
+            call!(send, "GET", "/readyz", "");
+            call!(send, "GET", "/metrics", "");
+
+            call!(send, "GET", format!("/api/v3/users/{}", alice), "");
+            let request = axum::http::Request::get("/api/v3/live")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let stream = tower::ServiceExt::oneshot(app.clone(), request)
+                .await
+                .unwrap();
+            assert_eq!(stream.status(), StatusCode::OK);
+            assert_eq!(stream.headers()["content-type"], "text/event-stream");
+            assert_eq!(failed, Vec::<String>::new());
+            let (_, metrics) = send(&app, "GET", "/metrics", "").await;
+            let metrics = metrics.as_str().unwrap();
+            assert!(metrics.contains(
+                r#"matekasse_http_requests_total{method="GET",route="/api/v3/users/:id",status="200"} 1"#
+            ));
+            assert!(metrics.contains(r#"route="/api/v3/users/:id/buy""#));
+            assert!(metrics.contains(r#"route="/readyz""#));
+            assert!(!metrics.contains("axum_nest"));
+            let events = entity::webhook_event::Entity::find()
+                .all(&db.orm)
+                .await
+                .unwrap();
+            assert!(!events.is_empty());
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! Import of users, drinks, barcodes and audits of a
+//! [Mete](https://github.com/chaosdorf/mete) installation.
+//!
+//! The data is read either from the sqlite database of mete or from a
+//! directory containing the json exports of its API (`users.json`,
+//! `drinks.json`, `barcodes.json` and `audits.json`). Every imported record is
+//! remembered in the `mete_import` table, so running the import again only
+//! adds records that are new since the last run.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime};
+use eyre::{eyre, Context, Result};
+use sea_orm::{entity::*, query::*, DatabaseTransaction, TransactionTrait};
+use serde::{Deserialize, Deserializer};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+    SqlitePool,
+};
+
+use crate::{
+    entity::{barcode, mete_import, product, transaction, user},
+    storage::Db,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeteUser {
+    pub id: i32,
+    pub name: String,
+    pub email: Option<String>,
+    #[serde(deserialize_with = "cents")]
+    pub balance: i32,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default)]
+    pub audit: bool,
+    #[serde(default = "default_true")]
+    pub redirect: bool,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeteDrink {
+    pub id: i32,
+    pub name: String,
+    /// bottle size in liter
+    pub bottle_size: Option<f64>,
+    /// mg of caffeine per 100 ml
+    pub caffeine: Option<i32>,
+    #[serde(deserialize_with = "cents")]
+    pub price: i32,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeteBarcode {
+    /// the barcode itself
+    pub id: String,
+    pub drink: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeteAudit {
+    pub id: i32,
+    #[serde(deserialize_with = "cents")]
+    pub difference: i32,
+    pub drink: Option<i32>,
+    pub user: Option<i32>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeteDump {
+    pub users: Vec<MeteUser>,
+    pub drinks: Vec<MeteDrink>,
+    pub barcodes: Vec<MeteBarcode>,
+    pub audits: Vec<MeteAudit>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn to_cents(value: f64) -> i32 {
+    (value * 100.0).round() as i32
+}
+
+/// mete serializes decimals as numbers or strings, depending on the version
+fn cents<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Decimal {
+        Number(f64),
+        String(String),
+    }
+
+    match Decimal::deserialize(deserializer)? {
+        Decimal::Number(value) => Ok(to_cents(value)),
+        Decimal::String(value) => value
+            .parse::<f64>()
+            .map(to_cents)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
+
+fn timestamp<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?
+        .as_deref()
+        .and_then(parse_timestamp))
+}
+
+/// rails stores booleans either as `t`/`f` or as `1`/`0`
+fn boolean(value: Option<String>, default: bool) -> bool {
+    match value.as_deref() {
+        Some("t") | Some("1") | Some("true") => true,
+        Some("f") | Some("0") | Some("false") => false,
+        _ => default,
+    }
+}
+
+async fn read_json<T>(dir: &Path, file: &str) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let path = dir.join(file);
+    let data = tokio::fs::read_to_string(&path)
+        .await
+        .wrap_err_with(|| eyre!("unable to read {}", path.display()))?;
+    serde_json::from_str(&data).wrap_err_with(|| eyre!("unable to parse {}", path.display()))
+}
+
+/// reads the json exports of the mete API from a directory
+pub async fn read_json_dump(dir: &Path) -> Result<MeteDump> {
+    /// `audits.json` wraps the audits together with their sums
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Audits {
+        List(Vec<MeteAudit>),
+        Wrapped { audits: Vec<MeteAudit> },
+    }
+
+    let audits = match read_json::<Audits>(dir, "audits.json").await? {
+        Audits::List(audits) | Audits::Wrapped { audits } => audits,
+    };
+
+    Ok(MeteDump {
+        users: read_json(dir, "users.json").await?,
+        drinks: read_json(dir, "drinks.json").await?,
+        barcodes: read_json(dir, "barcodes.json").await?,
+        audits,
+    })
+}
+
+/// reads the sqlite database of a mete installation
+pub async fn read_sqlite_dump(path: &Path) -> Result<MeteDump> {
+    // sqlx switches to WAL by default, which would require write access
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .journal_mode(SqliteJournalMode::Delete);
+    let pool = SqlitePool::connect_with(options)
+        .await
+        .wrap_err_with(|| eyre!("unable to open {}", path.display()))?;
+
+    type UserRow = (
+        i32,
+        String,
+        Option<String>,
+        Option<f64>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    );
+    let users = sqlx::query_as::<_, UserRow>(
+        "SELECT id, name, email, CAST(balance AS REAL), CAST(active AS TEXT), \
+         CAST(audit AS TEXT), CAST(redirect AS TEXT), CAST(created_at AS TEXT) FROM users",
+    )
+    .fetch_all(&pool)
+    .await
+    .wrap_err("unable to read users")?
+    .into_iter()
+    .map(
+        |(id, name, email, balance, active, audit, redirect, created_at)| MeteUser {
+            id,
+            name,
+            email: email.filter(|e| !e.is_empty()),
+            balance: to_cents(balance.unwrap_or_default()),
+            active: boolean(active, true),
+            audit: boolean(audit, false),
+            redirect: boolean(redirect, true),
+            created_at: created_at.as_deref().and_then(parse_timestamp),
+        },
+    )
+    .collect();
+
+    type DrinkRow = (
+        i32,
+        String,
+        Option<f64>,
+        Option<i32>,
+        Option<f64>,
+        Option<String>,
+        Option<String>,
+    );
+    let drinks = sqlx::query_as::<_, DrinkRow>(
+        "SELECT id, name, CAST(bottle_size AS REAL), caffeine, CAST(price AS REAL), \
+         CAST(active AS TEXT), CAST(created_at AS TEXT) FROM drinks",
+    )
+    .fetch_all(&pool)
+    .await
+    .wrap_err("unable to read drinks")?
+    .into_iter()
+    .map(
+        |(id, name, bottle_size, caffeine, price, active, created_at)| MeteDrink {
+            id,
+            name,
+            bottle_size,
+            caffeine,
+            price: to_cents(price.unwrap_or_default()),
+            active: boolean(active, true),
+            created_at: created_at.as_deref().and_then(parse_timestamp),
+        },
+    )
+    .collect();
+
+    let barcodes = sqlx::query_as::<_, (String, i32)>("SELECT id, drink FROM barcodes")
+        .fetch_all(&pool)
+        .await
+        .wrap_err("unable to read barcodes")?
+        .into_iter()
+        .map(|(id, drink)| MeteBarcode { id, drink })
+        .collect();
+
+    let audits = sqlx::query_as::<_, (i32, Option<f64>, Option<i32>, Option<i32>, Option<String>)>(
+        "SELECT id, CAST(difference AS REAL), drink, user, CAST(created_at AS TEXT) FROM audits",
+    )
+    .fetch_all(&pool)
+    .await
+    .wrap_err("unable to read audits")?
+    .into_iter()
+    .map(|(id, difference, drink, user, created_at)| MeteAudit {
+        id,
+        difference: to_cents(difference.unwrap_or_default()),
+        drink,
+        user,
+        created_at: created_at.as_deref().and_then(parse_timestamp),
+    })
+    .collect();
+
+    pool.close().await;
+
+    Ok(MeteDump {
+        users,
+        drinks,
+        barcodes,
+        audits,
+    })
+}
+
+/// reads a mete dump from a sqlite database or a directory of json exports
+pub async fn read_dump(source: &Path) -> Result<MeteDump> {
+    if source.is_dir() {
+        read_json_dump(source).await
+    } else {
+        read_sqlite_dump(source).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    /// records that were newly created
+    pub created: i32,
+    /// records mapped onto an existing row with the same name
+    pub linked: i32,
+    /// records imported by a previous run
+    pub skipped: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub users: Counts,
+    pub products: Counts,
+    pub barcodes: Counts,
+    pub audits: Counts,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "dry run, nothing was written to the database")?;
+        }
+        for (name, counts) in [
+            ("users", self.users),
+            ("products", self.products),
+            ("barcodes", self.barcodes),
+            ("audits", self.audits),
+        ] {
+            writeln!(
+                f,
+                "{:<9} created: {:>5}  linked: {:>5}  skipped: {:>5}",
+                name, counts.created, counts.linked, counts.skipped
+            )?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// local ids of already imported records of one kind
+async fn imported(
+    txn: &DatabaseTransaction,
+    kind: mete_import::Kind,
+) -> Result<HashMap<String, i32>> {
+    Ok(mete_import::Entity::find()
+        .filter(mete_import::Column::Kind.eq(kind))
+        .all(txn)
+        .await?
+        .into_iter()
+        .map(|m| (m.mete_id, m.local_id))
+        .collect())
+}
+
+async fn remember(
+    txn: &DatabaseTransaction,
+    mapping: &mut HashMap<String, i32>,
+    kind: mete_import::Kind,
+    mete_id: String,
+    local_id: i32,
+) -> Result<()> {
+    mete_import::ActiveModel {
+        kind: Set(kind),
+        mete_id: Set(mete_id.clone()),
+        local_id: Set(local_id),
+        ..Default::default()
+    }
+    .insert(txn)
+    .await?;
+    mapping.insert(mete_id, local_id);
+    Ok(())
+}
+
+fn created_at(timestamp: Option<NaiveDateTime>) -> ActiveValue<NaiveDateTime> {
+    timestamp
+        .map(ActiveValue::set)
+        .unwrap_or_else(ActiveValue::not_set)
+}
+
+async fn import_users(
+    txn: &DatabaseTransaction,
+    users: Vec<MeteUser>,
+    report: &mut ImportReport,
+) -> Result<HashMap<String, i32>> {
+    let mut mapping = imported(txn, mete_import::Kind::User).await?;
+    for mete in users {
+        if mapping.contains_key(&mete.id.to_string()) {
+            report.users.skipped += 1;
+            continue;
+        }
+
+        let existing = user::Entity::find()
+            .filter(user::Column::Name.eq(mete.name.clone()))
+            .one(txn)
+            .await?;
+        let local_id = match existing {
+            Some(existing) => {
+                report.users.linked += 1;
+                if existing.balance != mete.balance {
+                    report.warnings.push(format!(
+                        "user {:?} already exists with a different balance, keeping {} instead of {}",
+                        mete.name, existing.balance, mete.balance
+                    ));
+                }
+                existing.id
+            }
+            None => {
+                report.users.created += 1;
+                user::ActiveModel {
+                    name: Set(mete.name),
+                    email: Set(mete.email.filter(|e| !e.is_empty())),
+                    balance: Set(mete.balance),
+                    active: Set(mete.active),
+                    audit: Set(mete.audit),
+                    redirect: Set(mete.redirect),
+                    created_at: created_at(mete.created_at),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?
+                .id
+            }
+        };
+        remember(
+            txn,
+            &mut mapping,
+            mete_import::Kind::User,
+            mete.id.to_string(),
+            local_id,
+        )
+        .await?;
+    }
+    Ok(mapping)
+}
+
+async fn import_drinks(
+    txn: &DatabaseTransaction,
+    drinks: Vec<MeteDrink>,
+    report: &mut ImportReport,
+) -> Result<HashMap<String, i32>> {
+    let mut mapping = imported(txn, mete_import::Kind::Drink).await?;
+    for mete in drinks {
+        if mapping.contains_key(&mete.id.to_string()) {
+            report.products.skipped += 1;
+            continue;
+        }
+
+        let existing = product::Entity::find()
+            .filter(product::Column::Name.eq(mete.name.clone()))
+            .one(txn)
+            .await?;
+        let local_id = match existing {
+            Some(existing) => {
+                report.products.linked += 1;
+                existing.id
+            }
+            None => {
+                report.products.created += 1;
+                product::ActiveModel {
+                    name: Set(mete.name),
+                    caffeine: Set(mete.caffeine),
+                    volume: Set(mete
+                        .bottle_size
+                        .map(|liter| (liter * 1000.0).round() as i32)),
+                    price: Set(mete.price),
+                    active: Set(mete.active),
+                    created_at: created_at(mete.created_at),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?
+                .id
+            }
+        };
+        remember(
+            txn,
+            &mut mapping,
+            mete_import::Kind::Drink,
+            mete.id.to_string(),
+            local_id,
+        )
+        .await?;
+    }
+    Ok(mapping)
+}
+
+async fn import_barcodes(
+    txn: &DatabaseTransaction,
+    barcodes: Vec<MeteBarcode>,
+    drinks: &HashMap<String, i32>,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let mut mapping = imported(txn, mete_import::Kind::Barcode).await?;
+    for mete in barcodes {
+        if mapping.contains_key(&mete.id) {
+            report.barcodes.skipped += 1;
+            continue;
+        }
+        let product = match drinks.get(&mete.drink.to_string()) {
+            Some(product) => *product,
+            None => {
+                report.warnings.push(format!(
+                    "barcode {} belongs to unknown drink {}",
+                    mete.id, mete.drink
+                ));
+                continue;
+            }
+        };
+
+        let existing = barcode::Entity::find()
+            .filter(barcode::Column::Code.eq(mete.id.clone()))
+            .one(txn)
+            .await?;
+        let local_id = match existing {
+            Some(existing) => {
+                report.barcodes.linked += 1;
+                existing.id
+            }
+            None => {
+                report.barcodes.created += 1;
+                barcode::ActiveModel {
+                    code: Set(mete.id.clone()),
+                    product: Set(Some(product)),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?
+                .id
+            }
+        };
+        remember(
+            txn,
+            &mut mapping,
+            mete_import::Kind::Barcode,
+            mete.id,
+            local_id,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Audits become ledger entries. They only describe the history, the balance
+/// of the users is taken from the users themselves.
+async fn import_audits(
+    txn: &DatabaseTransaction,
+    audits: Vec<MeteAudit>,
+    users: &HashMap<String, i32>,
+    drinks: &HashMap<String, i32>,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let mut mapping = imported(txn, mete_import::Kind::Audit).await?;
+    for mete in audits {
+        if mapping.contains_key(&mete.id.to_string()) {
+            report.audits.skipped += 1;
+            continue;
+        }
+        let user = match mete.user.and_then(|u| users.get(&u.to_string())) {
+            Some(user) => *user,
+            None => {
+                report.warnings.push(format!(
+                    "audit {} belongs to unknown user {:?}",
+                    mete.id, mete.user
+                ));
+                continue;
+            }
+        };
+        let product = mete.drink.and_then(|d| drinks.get(&d.to_string())).copied();
+        let kind = match (mete.difference < 0, product) {
+            (true, Some(_)) => transaction::Kind::Purchase,
+            (true, None) => transaction::Kind::Spend,
+            (false, _) => transaction::Kind::Deposit,
+        };
+
+        report.audits.created += 1;
+        let local_id = transaction::ActiveModel {
+            kind: Set(kind),
+            user: Set(Some(user)),
+            product: Set(product),
+            amount: Set(mete.difference),
+            created_at: created_at(mete.created_at),
+            reference: Set(Some(format!("mete audit {}", mete.id))),
+            ..Default::default()
+        }
+        .insert(txn)
+        .await?
+        .id;
+        remember(
+            txn,
+            &mut mapping,
+            mete_import::Kind::Audit,
+            mete.id.to_string(),
+            local_id,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Imports a mete dump. Records are matched by name onto existing users and
+/// products. A dry run performs the whole import in a transaction which is
+/// rolled back afterwards.
+pub async fn import(db: &Db, dump: MeteDump, dry_run: bool) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let txn = db.orm.begin().await?;
+    let users = import_users(&txn, dump.users, &mut report).await?;
+    let drinks = import_drinks(&txn, dump.drinks, &mut report).await?;
+    import_barcodes(&txn, dump.barcodes, &drinks, &mut report).await?;
+    import_audits(&txn, dump.audits, &users, &drinks, &mut report).await?;
+
+    if dry_run {
+        txn.rollback().await?;
+    } else {
+        txn.commit().await?;
+    }
+
+    Ok(report)
+}
+
+/// entry point of the `import-mete` command
+pub async fn run(db: &Db, source: PathBuf, dry_run: bool) -> Result<()> {
+    let dump = read_dump(&source).await?;
+    let report = import(db, dump, dry_run).await?;
+    print!("{}", report);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_api_json() {
+        let users: Vec<MeteUser> = serde_json::from_str(
+            r#"[{"id": 3, "name": "alice", "email": "", "balance": "-1.5",
+                 "active": true, "audit": false, "redirect": true,
+                 "created_at": "2016-05-01T12:30:00.000+02:00"}]"#,
+        )
+        .unwrap();
+        let audits: Vec<MeteAudit> = serde_json::from_str(
+            r#"[{"id": 1, "difference": -1.5, "drink": 2, "user": 3, "created_at": null}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            users,
+            vec![MeteUser {
+                id: 3,
+                name: "alice".to_string(),
+                email: Some(String::new()),
+                balance: -150,
+                active: true,
+                audit: false,
+                redirect: true,
+                created_at: Some(NaiveDate::from_ymd(2016, 5, 1).and_hms(10, 30, 0)),
+            }]
+        );
+        assert_eq!(audits[0].difference, -150);
+        assert_eq!(audits[0].created_at, None);
+    }
+
+    #[test]
+    fn rails_timestamps_and_booleans() {
+        assert_eq!(
+            parse_timestamp("2014-02-03 04:05:06.789"),
+            Some(NaiveDate::from_ymd(2014, 2, 3).and_hms_milli(4, 5, 6, 789))
+        );
+        assert!(boolean(Some("t".to_string()), false));
+        assert!(!boolean(Some("0".to_string()), true));
+        assert!(boolean(None, true));
+    }
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, time::Instant};
+
+use axum::{
+    extract::{Extension, MatchedPath},
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{Headers, IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sea_orm::{entity::*, query::*};
+
+use crate::{
+    entity::{
+        product,
+        transaction::{self, Kind},
+        user,
+    },
+    storage::Db,
+    utils::Result,
+};
+
+/// Everything exported at `/metrics`. Request and query metrics are recorded
+/// as they happen, the business metrics are read from the database on every
+/// scrape.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    query_duration: HistogramVec,
+    balance: IntGauge,
+    active_users: IntGauge,
+    purchases: IntCounterVec,
+    stock: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("matekasse".to_owned()), None)?,
+            requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "handled http requests"),
+                &["method", "route", "status"],
+            )?,
+            request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "time until the response was ready",
+                ),
+                &["method", "route"],
+            )?,
+            query_duration: HistogramVec::new(
+                HistogramOpts::new("db_query_duration_seconds", "execution time of queries")
+                    .buckets(vec![
+                        0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5,
+                    ]),
+                &["statement"],
+            )?,
+            balance: IntGauge::new("balance_cents", "sum of the balances of all users")?,
+            active_users: IntGauge::new(
+                "users_active",
+                "users that are neither inactive nor archived",
+            )?,
+            purchases: IntCounterVec::new(
+                Opts::new("purchases_total", "items sold per product"),
+                &["product", "name"],
+            )?,
+            stock: IntGaugeVec::new(
+                Opts::new("stock", "items in stock of products that keep track of it"),
+                &["product", "name"],
+            )?,
+        };
+
+        metrics
+            .registry
+            .register(Box::new(metrics.requests.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.request_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.query_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.balance.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.active_users.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.purchases.clone()))?;
+        metrics.registry.register(Box::new(metrics.stock.clone()))?;
+        Ok(metrics)
+    }
+
+    /// records a query executed by sea-orm, meant as its metric callback
+    pub fn observe_query(&self, info: &sea_orm::metric::Info<'_>) {
+        self.query_duration
+            .with_label_values(&[statement(&info.statement.sql)])
+            .observe(info.elapsed.as_secs_f64());
+    }
+
+    /// reads the business metrics from the database
+    async fn update(&self, db: &Db) -> Result<()> {
+        let users = user::Entity::find().all(&db.orm).await?;
+        self.balance
+            .set(users.iter().map(|u| i64::from(u.balance)).sum());
+        self.active_users.set(
+            users
+                .iter()
+                .filter(|u| u.active && u.archived_at.is_none())
+                .count() as i64,
+        );
+
+        let mut sold = HashMap::<i32, u64>::new();
+        for t in transaction::Entity::find()
+            .filter(transaction::Column::Kind.eq(Kind::Purchase))
+            .all(&db.orm)
+            .await?
+        {
+            if let Some(product) = t.product {
+                *sold.entry(product).or_default() += 1;
+            }
+        }
+
+        // deleted products should disappear from the stock levels
+        self.stock.reset();
+        for product in product::Entity::find().all(&db.orm).await? {
+            let id = product.id.to_string();
+            let labels = [id.as_str(), product.name.as_str()];
+            let counter = self.purchases.with_label_values(&labels);
+            // the counter only ever moves forward, even if it was read before
+            let count = sold.get(&product.id).copied().unwrap_or_default();
+            counter.inc_by(count.saturating_sub(counter.get()));
+            if let Some(stock) = product.stock {
+                self.stock.with_label_values(&labels).set(stock.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// kind of an sql statement, e.g. `select`, to keep the number of labels small
+fn statement(sql: &str) -> &'static str {
+    let keyword = sql.split_whitespace().next().unwrap_or_default();
+    [
+        "select", "insert", "update", "delete", "begin", "commit", "rollback",
+    ]
+    .into_iter()
+    .find(|k| keyword.eq_ignore_ascii_case(k))
+    .unwrap_or("other")
+}
+
+/// Middleware counting requests and their duration by route. Has to be added
+/// as route layer, otherwise the route is not known yet.
+pub(crate) async fn track<B>(req: Request<B>, next: Next<B>) -> Response {
+    let metrics = req.extensions().get::<Metrics>().cloned();
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => req.uri().path().to_owned(),
+    };
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    if let Some(metrics) = metrics {
+        metrics
+            .request_duration
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+        metrics
+            .requests
+            .with_label_values(&[&method, &route, response.status().as_str()])
+            .inc();
+    }
+    response
+}
+
+/// metrics in the prometheus text format
+pub(crate) async fn export(
+    Extension(db): Extension<Db>,
+    Extension(metrics): Extension<Metrics>,
+) -> Result<impl IntoResponse> {
+    metrics.update(&db).await?;
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(eyre::Error::from)?;
+    Ok((
+        Headers([(CONTENT_TYPE, encoder.format_type().to_owned())]),
+        buffer,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn queries_are_labeled_by_statement() {
+        assert_eq!(
+            statement("SELECT \"users\".\"id\" FROM \"users\""),
+            "select"
+        );
+        assert_eq!(statement("  insert into products"), "insert");
+        assert_eq!(statement("PRAGMA foreign_keys = ON"), "other");
+
+        let metrics = Metrics::new().unwrap();
+        metrics
+            .requests
+            .with_label_values(&["GET", "/users", "200"])
+            .inc();
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&metrics.registry.gather(), &mut buffer)
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains(
+            r#"matekasse_http_requests_total{method="GET",route="/users",status="200"} 1"#
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc, Weekday};
 
 #[derive(Debug, Clone, Serialize, PartialEq, Deserialize)]
 pub struct Product {
@@ -18,12 +18,21 @@ pub struct Product {
     /// g sugar per 100g / 100ml with one decimal place
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sugar: Option<i32>,
+    /// package size in ml / g
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<i32>,
     pub price: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub active: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<i32>,
+    /// only age verified users may buy this product
+    #[serde(default)]
+    pub age_restricted: bool,
+    /// number of items in stock, unset if the stock is not tracked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock: Option<i32>,
 }
 
 impl Default for Product {
@@ -38,9 +47,12 @@ impl Default for Product {
             alcohol: Default::default(),
             energy: Default::default(),
             sugar: Default::default(),
+            volume: Default::default(),
             price: 150,
             active: true,
             image: Default::default(),
+            age_restricted: Default::default(),
+            stock: Default::default(),
         }
     }
 }
@@ -60,12 +72,20 @@ pub struct ProductCreateRequest {
     /// g sugar per 100g / 100ml with one decimal place
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sugar: Option<i32>,
+    /// package size in ml / g
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub price: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub active: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<i32>,
+    /// defaults to `true` for products containing alcohol
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_restricted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Deserialize)]
@@ -84,12 +104,19 @@ pub struct ProductEditRequest {
     /// g sugar per 100g / 100ml with one decimal place
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sugar: Option<i32>,
+    /// package size in ml / g
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub price: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub active: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_restricted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock: Option<i32>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -261,6 +288,21 @@ pub struct User {
     pub redirect: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub avatar: Option<i32>,
+    /// user is allowed to buy age restricted products
+    #[serde(default)]
+    pub age_verified: bool,
+    /// temporary users belong to an event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<i32>,
+    /// archived duplicate, everything was moved to this user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<i32>,
+    /// archived users are kept for the history only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// user wants to receive reminders and statements by email
+    #[serde(default = "default_true")]
+    pub notifications: bool,
 }
 
 impl Default for User {
@@ -270,6 +312,7 @@ impl Default for User {
             updated_at: Utc::now(),
             active: default_true(),
             redirect: default_true(),
+            notifications: default_true(),
 
             id: Default::default(),
             name: Default::default(),
@@ -278,6 +321,10 @@ impl Default for User {
             barcode: Default::default(),
             audit: Default::default(),
             avatar: Default::default(),
+            age_verified: Default::default(),
+            event: Default::default(),
+            merged_into: Default::default(),
+            archived_at: Default::default(),
         }
     }
 }
@@ -301,6 +348,11 @@ pub struct UserCreateRequest {
     pub redirect: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub avatar: Option<i32>,
+    /// can only be set with admin privileges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<bool>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -319,6 +371,32 @@ pub struct UserEditRequest {
     pub redirect: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub avatar: Option<i32>,
+    /// can only be set with admin privileges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<bool>,
+}
+
+/// a row of a bulk user import that could not be imported
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct UserImportError {
+    /// line in the csv file, the header is line 1
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct UserImportResponse {
+    pub imported: Vec<User>,
+    /// if any row contains an error, no user is imported at all
+    pub errors: Vec<UserImportError>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UserMergeRequest {
+    /// user that is merged into the one given in the path and archived
+    pub source: i32,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -333,3 +411,424 @@ pub struct UsersStatsResponce {
     pub active_count: i32,
     pub balance_sum: i32,
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StatsQuery {
+    /// first day (inclusive, local time) to take into account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    /// last day (inclusive, local time) to take into account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<Bucket>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ProductStats {
+    pub product: i32,
+    pub name: String,
+    pub count: i32,
+    /// revenue in cent
+    pub revenue: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct HourStats {
+    /// hour of the day in local time
+    pub hour: u32,
+    pub count: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WeekdayStats {
+    pub weekday: Weekday,
+    pub count: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TimeSeriesEntry {
+    /// first day of the bucket
+    pub start: NaiveDate,
+    pub count: i32,
+    /// sum in cent
+    pub amount: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct TopConsumer {
+    pub user: i32,
+    pub name: String,
+    pub count: i32,
+    /// money spent in cent
+    pub amount: i32,
+}
+
+/// amount of nutrients consumed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Intake {
+    /// mg of caffeine
+    pub caffeine: i32,
+    /// g of sugar with one decimal place
+    pub sugar: i32,
+    /// ml of pure alcohol with one decimal place
+    pub alcohol: i32,
+    /// energy without decimal places
+    pub energy: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct IntakeEntry {
+    /// first day of the period
+    pub start: NaiveDate,
+    #[serde(flatten)]
+    pub intake: Intake,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct NutritionResponse {
+    pub daily: Vec<IntakeEntry>,
+    pub weekly: Vec<IntakeEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Nutrient {
+    Caffeine,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct IntakeWarning {
+    pub nutrient: Nutrient,
+    /// intake of today
+    pub intake: i32,
+    /// configured daily limit
+    pub limit: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BuyResponse {
+    #[serde(flatten)]
+    pub user: User,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<IntakeWarning>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CashboxEntry {
+    pub id: i32,
+    pub kind: crate::entity::cashbox::Kind,
+    /// amount in cent
+    pub amount: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CashboxRequest {
+    /// amount in cent
+    pub amount: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CashboxStatus {
+    /// expected content of the cashbox in cent
+    pub expected: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_count: Option<CashboxEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Reconciliation {
+    pub counted_at: DateTime<Utc>,
+    /// expected content at the time of the count in cent
+    pub expected: i32,
+    /// counted content in cent
+    pub counted: i32,
+    /// `counted - expected`, negative if money is missing
+    pub discrepancy: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BankTransaction {
+    pub id: i32,
+    pub bank_id: String,
+    pub booked_at: NaiveDate,
+    /// amount in cent
+    pub amount: i32,
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub status: crate::entity::bank_transaction::Status,
+    /// proposed user while pending, booked user once accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    Camt,
+    Csv,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BankImportQuery {
+    pub format: StatementFormat,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct BankImportResponse {
+    /// lines that were already imported before
+    pub duplicates: i32,
+    /// outgoing payments, which are not imported
+    pub skipped: i32,
+    /// newly imported lines waiting for review
+    pub imported: Vec<BankTransaction>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct BankAcceptRequest {
+    /// book the deposit for this user instead of the proposed one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Event {
+    pub id: i32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// closed events do not accept new users and purchases
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EventCreateRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct EventUserSettlement {
+    pub user: i32,
+    pub name: String,
+    /// sum of all purchases during the event in cent
+    pub spent: i32,
+    pub balance: i32,
+    /// amount the user still has to pay, i.e. the negative balance
+    pub owes: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EventSettlement {
+    pub event: Event,
+    pub users: Vec<EventUserSettlement>,
+    pub products: Vec<ProductStats>,
+    /// revenue of all purchases in cent
+    pub revenue: i32,
+    /// sum of all outstanding debts in cent
+    pub owed: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GroupAccount {
+    pub id: i32,
+    pub name: String,
+    pub balance: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct GroupMember {
+    pub user: i32,
+    /// maximum the member may charge to the group in cent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap: Option<i32>,
+    /// sum of all purchases the member charged to the group in cent
+    pub spent: i32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GroupCreateRequest {
+    pub name: String,
+}
+
+/// adds a member or changes the cap of an existing one
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GroupMemberRequest {
+    pub user: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GroupBuyRequest {
+    /// acting member
+    pub user: i32,
+    pub product: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecurringCharge {
+    pub id: i32,
+    pub name: String,
+    /// charged per period in cent
+    pub amount: i32,
+    pub interval: crate::entity::recurring_charge::Interval,
+    /// first period
+    pub starts_on: NaiveDate,
+    pub users: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_account: Option<i32>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecurringChargeRequest {
+    pub name: String,
+    pub amount: i32,
+    pub interval: crate::entity::recurring_charge::Interval,
+    /// defaults to today
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starts_on: Option<NaiveDate>,
+    #[serde(default)]
+    pub users: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_account: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct NotificationRunResponse {
+    /// number of emails sent
+    pub sent: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RecurringRunResponse {
+    /// number of periods booked
+    pub booked: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanupAction {
+    Deactivate,
+    /// deactivate and archive users without balance
+    Archive,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InactiveUser {
+    pub user: i32,
+    pub name: String,
+    pub balance: i32,
+    /// last purchase, deposit or the like, or the creation of the user
+    pub last_activity: DateTime<Utc>,
+    pub action: CleanupAction,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CleanupQuery {
+    /// overrides the configured number of days without activity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CleanupReport {
+    /// users without activity since this point in time are cleaned up
+    pub cutoff: DateTime<Utc>,
+    pub users: Vec<InactiveUser>,
+}
+
+/// body of every webhook request
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WebhookPayload {
+    /// id of the event, the same for every retry
+    pub id: i32,
+    pub event: crate::entity::webhook_event::Kind,
+    pub created_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+/// a single transaction as published to webhooks and the live stream
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    pub id: i32,
+    pub kind: crate::entity::transaction::Kind,
+    /// missing for anonymous sales and group deposits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<i32>,
+    pub amount: i32,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_account: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+/// data of transfer events
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WebhookTransfer {
+    pub sender: i32,
+    pub receiver: i32,
+    pub amount: i32,
+}
+
+/// a change pushed to clients of the live stream
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum LiveEvent {
+    User(User),
+    UserDeleted(i32),
+    Product(Product),
+    ProductDeleted(i32),
+    Transaction(LedgerEntry),
+}
+
+/// sales of the current day as published to mqtt
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SalesTotals {
+    pub date: NaiveDate,
+    pub purchases: i32,
+    /// in cent
+    pub revenue: i32,
+}
+
+/// result of the readiness probe
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Readiness {
+    /// the database answered a query
+    pub database: bool,
+    /// versions of migrations missing in the database
+    pub pending_migrations: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BackupInfo {
+    pub file: String,
+    /// in bytes
+    pub size: u64,
+}
@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
+use sea_orm::{entity::*, query::*};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::{
+    config::{MqttConfig, MqttTopics},
+    entity::{
+        product,
+        transaction::{self, Kind},
+    },
+    live::Live,
+    models::{LiveEvent, Product, SalesTotals},
+    stats::in_range,
+    storage::Db,
+    utils::Result,
+};
+
+/// how often the totals are published besides after every purchase, this also
+/// resets them once the day is over
+const TOTALS_INTERVAL: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// messages queued while the broker is unreachable
+const CAPACITY: usize = 64;
+
+/// connection options, `None` if no broker is configured
+fn options(config: &MqttConfig) -> Option<MqttOptions> {
+    let host = config.host.as_ref()?;
+    let mut options = MqttOptions::new(&config.client_id, host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        options.set_credentials(username, password);
+    }
+    Some(options)
+}
+
+fn product_topic(topics: &MqttTopics, id: i32) -> String {
+    topics.products.replace("{id}", &id.to_string())
+}
+
+fn totals(date: NaiveDate, purchases: &[transaction::Model]) -> SalesTotals {
+    SalesTotals {
+        date,
+        purchases: purchases.len() as i32,
+        revenue: -purchases.iter().map(|t| t.amount).sum::<i32>(),
+    }
+}
+
+struct Publisher {
+    db: Db,
+    topics: MqttTopics,
+    client: AsyncClient,
+}
+
+impl Publisher {
+    async fn publish(&self, topic: String, retain: bool, payload: &impl Serialize) -> Result<()> {
+        let payload = serde_json::to_vec(payload).map_err(eyre::Error::from)?;
+        self.client
+            .publish(topic, QoS::AtLeastOnce, retain, payload)
+            .await
+            .map_err(eyre::Error::from)?;
+        Ok(())
+    }
+
+    /// the state of every product, so subscribers see the current stock right away
+    async fn products(&self) -> Result<()> {
+        for product in product::Entity::find().all(&self.db.orm).await? {
+            let product = Product::from(product);
+            self.publish(product_topic(&self.topics, product.id), true, &product)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn totals(&self) -> Result<()> {
+        let today = Local::today().naive_local();
+        let purchases = in_range(
+            transaction::Entity::find().filter(transaction::Column::Kind.eq(Kind::Purchase)),
+            transaction::Column::CreatedAt,
+            Some(today),
+            Some(today),
+        )
+        .all(&self.db.orm)
+        .await?;
+        self.publish(self.topics.totals.clone(), true, &totals(today, &purchases))
+            .await
+    }
+
+    async fn handle(&self, event: LiveEvent) -> Result<()> {
+        match event {
+            LiveEvent::Product(product) => {
+                self.publish(product_topic(&self.topics, product.id), true, &product)
+                    .await
+            }
+            // an empty retained message removes the stored one
+            LiveEvent::ProductDeleted(id) => {
+                self.client
+                    .publish(
+                        product_topic(&self.topics, id),
+                        QoS::AtLeastOnce,
+                        true,
+                        Vec::new(),
+                    )
+                    .await
+                    .map_err(eyre::Error::from)?;
+                Ok(())
+            }
+            LiveEvent::Transaction(entry) if entry.kind == Kind::Purchase => {
+                self.publish(self.topics.purchases.clone(), false, &entry)
+                    .await?;
+                self.totals().await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// keeps the connection to the broker alive, reconnecting after errors
+async fn connection(mut eventloop: EventLoop) {
+    loop {
+        if let Err(err) = eventloop.poll().await {
+            warn!("mqtt connection failed: {}", err);
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+/// Background task publishing purchases, product states and the totals of the
+/// day to the broker, does nothing if no broker is configured.
+pub async fn publisher(db: Db, config: MqttConfig, live: Live) {
+    let options = match options(&config) {
+        Some(options) => options,
+        None => return,
+    };
+    let (client, eventloop) = AsyncClient::new(options, CAPACITY);
+    tokio::spawn(connection(eventloop));
+
+    let publisher = Publisher {
+        db,
+        topics: config.topics,
+        client,
+    };
+    let mut events = live.subscribe();
+    if let Err(err) = publisher.products().await {
+        warn!("unable to publish products to mqtt: {:?}", err);
+    }
+
+    let mut interval = tokio::time::interval(TOTALS_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            _ = interval.tick() => publisher.totals().await,
+            event = events.recv() => match event {
+                Ok(event) => publisher.handle(event).await,
+                // some stock changes were missed
+                Err(RecvError::Lagged(_)) => publisher.products().await,
+                Err(RecvError::Closed) => return,
+            },
+        };
+        if let Err(err) = result {
+            warn!("unable to publish to mqtt: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Extension;
+    use pretty_assertions::assert_eq;
+    use rumqttc::{Event, Packet};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        entity::{group_account, group_member, user},
+        groups,
+        repository::Repositories,
+        testing::{self, send},
+    };
+
+    #[test]
+    fn topics_and_totals() {
+        let purchase = |id, amount| transaction::Model {
+            id,
+            kind: Kind::Purchase,
+            user: None,
+            product: Some(1),
+            amount,
+            created_at: NaiveDate::from_ymd(2022, 6, 3).and_hms(18, 0, 0),
+            reference: None,
+            event: None,
+            group_account: None,
+        };
+        let date = NaiveDate::from_ymd(2022, 6, 3);
+
+        assert_eq!(
+            product_topic(&MqttTopics::default(), 7),
+            "matekasse/products/7"
+        );
+        assert_eq!(
+            totals(date, &[purchase(1, -150), purchase(2, -200)]),
+            SalesTotals {
+                date,
+                purchases: 2,
+                revenue: 350
+            }
+        );
+    }
+
+    /// Needs a broker given in `MATEKASSE_TEST_MQTT`, e.g. `localhost:1883`,
+    /// does nothing without one.
+    #[tokio::test]
+    async fn group_purchases_reach_the_broker() {
+        let broker = match std::env::var("MATEKASSE_TEST_MQTT") {
+            Ok(broker) => broker,
+            Err(_) => return,
+        };
+        let (host, port) = broker.rsplit_once(':').expect("broker without port");
+        let port = port.parse().unwrap();
+
+        for db in testing::backends("mqtt").await {
+            // retained messages of earlier runs must not get in the way
+            let prefix = format!("matekasse-test-{}", chrono::Utc::now().timestamp_nanos());
+            let config = MqttConfig {
+                host: Some(host.to_owned()),
+                port,
+                client_id: prefix.clone(),
+                topics: MqttTopics {
+                    purchases: format!("{}/purchases", prefix),
+                    products: format!("{}/products/{{id}}", prefix),
+                    totals: format!("{}/totals", prefix),
+                },
+                ..Default::default()
+            };
+            let options = MqttOptions::new(format!("{}-subscriber", prefix), host, port);
+            let (subscriber, mut messages) = AsyncClient::new(options, CAPACITY);
+            subscriber
+                .subscribe(format!("{}/#", prefix), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            while !matches!(
+                messages.poll().await,
+                Ok(Event::Incoming(Packet::SubAck(_)))
+            ) {}
+
+            let product = product::ActiveModel {
+                name: Set("Club-Mate".to_owned()),
+                price: Set(150),
+                active: Set(true),
+                age_restricted: Set(false),
+                stock: Set(Some(10)),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            let user = user::ActiveModel {
+                name: Set("alice".to_owned()),
+                balance: Set(0),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            let group = group_account::ActiveModel {
+                name: Set("hackspace".to_owned()),
+                balance: Set(1000),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            group_member::ActiveModel {
+                group_account: Set(group.id),
+                user: Set(user.id),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+
+            let live = Live::default();
+            tokio::spawn(publisher(db.clone(), config, live.clone()));
+            let app = Repositories::database(db.clone())
+                .layer(groups::router())
+                .layer(Extension(testing::config()))
+                .layer(Extension(live))
+                .layer(Extension(db.clone()));
+
+            let mut received = Vec::new();
+            let mut bought = false;
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+            // the totals are published last, after the stock and the purchase
+            let sold = |(topic, totals): &(String, serde_json::Value)| {
+                topic == "/totals" && totals["purchases"] == json!(1)
+            };
+            while !received.iter().any(sold) {
+                let event = tokio::time::timeout_at(deadline, messages.poll())
+                    .await
+                    .expect("not all messages were published")
+                    .unwrap();
+                if let Event::Incoming(Packet::Publish(message)) = event {
+                    let payload: serde_json::Value =
+                        serde_json::from_slice(&message.payload).unwrap();
+                    let topic = message.topic.trim_start_matches(&prefix).to_owned();
+                    received.push((topic, payload));
+                }
+                // the purchase is made once the publisher is connected
+                if !bought && !received.is_empty() {
+                    let body = json!({ "user": user.id, "product": product.id }).to_string();
+                    let path = format!("/{}/buy", group.id);
+                    assert_eq!(send(&app, "POST", &path, body).await.0, 200);
+                    bought = true;
+                }
+            }
+
+            let product_topic = format!("/products/{}", product.id);
+            let stock = received
+                .iter()
+                .filter(|(topic, _)| topic == &product_topic)
+                .map(|(_, product)| product["stock"].clone())
+                .collect::<Vec<_>>();
+            assert_eq!(stock, vec![json!(10), json!(9)]);
+            let purchase = received
+                .iter()
+                .find(|(topic, _)| topic == "/purchases")
+                .map(|(_, purchase)| purchase)
+                .expect("purchase was not published");
+            assert_eq!(purchase["amount"], json!(-150));
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{extract::Extension, routing, Json, Router};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use sea_orm::{entity::*, query::*, ConnectionTrait, FromQueryResult};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Admin,
+    config::{Config, NotificationConfig, Template},
+    entity::{
+        notification::{self, Kind},
+        transaction, user,
+    },
+    journal::amount,
+    models::NotificationRunResponse,
+    stats::{in_range, to_local},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// how often the scheduler looks for users to notify
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+type Transport = AsyncSmtpTransport<Tokio1Executor>;
+
+pub fn router() -> Router {
+    Router::new().route("/run", routing::post(run))
+}
+
+/// the smtp transport, `None` if no smtp server is configured
+fn transport(config: &NotificationConfig) -> eyre::Result<Option<Transport>> {
+    let host = match &config.smtp_host {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+
+    let mut builder = if config.starttls {
+        Transport::starttls_relay(host)?
+    } else {
+        Transport::builder_dangerous(host)
+    }
+    .port(config.smtp_port);
+    if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(Some(builder.build()))
+}
+
+/// replaces every `{key}` in the template by its value
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_owned(), |text, (key, value)| {
+            text.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+/// Notifications the user is due, given when each kind was sent last and the
+/// last time money was added to the account.
+fn due(
+    config: &NotificationConfig,
+    user: &user::Model,
+    sent: &HashMap<Kind, NaiveDateTime>,
+    last_deposit: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> Vec<Kind> {
+    let mut kinds = Vec::new();
+
+    if let Some(threshold) = config.low_balance {
+        let reminded = match (sent.get(&Kind::LowBalance), last_deposit) {
+            (Some(sent), Some(deposit)) => *sent > deposit,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if user.balance < threshold && !reminded {
+            kinds.push(Kind::LowBalance);
+        }
+    }
+
+    if let Some(days) = config.debt_reminder_days {
+        let reminded = matches!(
+            sent.get(&Kind::Debt),
+            Some(sent) if now - *sent < chrono::Duration::days(days.into())
+        );
+        if user.balance < 0 && !reminded {
+            kinds.push(Kind::Debt);
+        }
+    }
+
+    if config.monthly_statement {
+        let month = |timestamp| {
+            let date = to_local(timestamp).date();
+            (date.year(), date.month())
+        };
+        let this_month = month(now);
+        let sent = sent.get(&Kind::Statement).map(|sent| month(*sent));
+        if month(user.created_at) != this_month && sent != Some(this_month) {
+            kinds.push(Kind::Statement);
+        }
+    }
+
+    kinds
+}
+
+/// first and last day of the month before the one `today` lies in
+fn last_month(today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let end = today.with_day(1).unwrap_or(today).pred();
+    (end.with_day(1).unwrap_or(end), end)
+}
+
+/// latest booking that raised the balance of a user
+#[derive(Debug, FromQueryResult)]
+struct LastDeposit {
+    user: i32,
+    last_deposit: NaiveDateTime,
+}
+
+/// when money was added to the account of each user the last time
+async fn last_deposits(db: &impl ConnectionTrait) -> Result<HashMap<i32, NaiveDateTime>> {
+    Ok(transaction::Entity::find()
+        .select_only()
+        .column(transaction::Column::User)
+        .column_as(transaction::Column::CreatedAt.max(), "last_deposit")
+        .filter(transaction::Column::User.is_not_null())
+        .filter(transaction::Column::Amount.gt(0))
+        .filter(transaction::Column::GroupAccount.is_null())
+        .group_by(transaction::Column::User)
+        .into_model::<LastDeposit>()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|d| (d.user, d.last_deposit))
+        .collect())
+}
+
+struct Notifier<'a> {
+    db: &'a Db,
+    config: &'a NotificationConfig,
+    transport: &'a Transport,
+}
+
+impl Notifier<'_> {
+    /// Renders the template for the user, returns `None` if there is nothing
+    /// worth sending.
+    async fn compose(&self, user: &user::Model, kind: Kind) -> Result<Option<(String, String)>> {
+        let mut values = vec![
+            ("name", user.name.clone()),
+            ("balance", amount(user.balance)),
+            ("threshold", amount(self.config.low_balance.unwrap_or(0))),
+        ];
+        let template = match kind {
+            Kind::LowBalance => &self.config.templates.low_balance,
+            Kind::Debt => &self.config.templates.debt,
+            Kind::Statement => {
+                let (from, to) = last_month(to_local(Utc::now().naive_utc()).date());
+                let transactions = in_range(
+                    transaction::Entity::find()
+                        .filter(transaction::Column::User.eq(user.id))
+                        .filter(transaction::Column::GroupAccount.is_null()),
+                    transaction::Column::CreatedAt,
+                    Some(from),
+                    Some(to),
+                )
+                .all(&self.db.orm)
+                .await?;
+                if transactions.is_empty() {
+                    return Ok(None);
+                }
+                let sum = |positive: bool| {
+                    transactions
+                        .iter()
+                        .map(|t| t.amount)
+                        .filter(|amount| (*amount > 0) == positive)
+                        .sum::<i32>()
+                        .abs()
+                };
+                values.push(("month", from.format("%Y-%m").to_string()));
+                values.push(("spent", amount(sum(false))));
+                values.push(("deposited", amount(sum(true))));
+                &self.config.templates.statement
+            }
+        };
+        let Template { subject, body } = template;
+        Ok(Some((render(subject, &values), render(body, &values))))
+    }
+
+    /// remembers that the user got the notification, so it's not due again
+    async fn record(&self, user: &user::Model, kind: Kind) -> Result<()> {
+        notification::ActiveModel {
+            user: Set(user.id),
+            kind: Set(kind),
+            ..Default::default()
+        }
+        .insert(&self.db.orm)
+        .await?;
+        Ok(())
+    }
+
+    /// Sends a single email and records it, returns false if there was nothing
+    /// to send. Empty statements are recorded as well, they would be composed
+    /// again on every run otherwise.
+    async fn send(&self, user: &user::Model, email: &str, kind: Kind) -> Result<bool> {
+        let (subject, body) = match self.compose(user, kind).await? {
+            Some(message) => message,
+            None => {
+                self.record(user, kind).await?;
+                return Ok(false);
+            }
+        };
+
+        let message = Message::builder()
+            .from(self.config.from.parse().map_err(eyre::Error::from)?)
+            .to(Mailbox::new(
+                Some(user.name.clone()),
+                email.parse().map_err(eyre::Error::from)?,
+            ))
+            .subject(subject)
+            .body(body)
+            .map_err(eyre::Error::from)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(eyre::Error::from)?;
+
+        self.record(user, kind).await?;
+        Ok(true)
+    }
+
+    /// sends every notification that is due, returns the number of emails sent
+    async fn run_due(&self) -> Result<i32> {
+        let users = user::Entity::find()
+            .filter(user::Column::Notifications.eq(true))
+            .filter(user::Column::Active.eq(true))
+            .filter(user::Column::Email.is_not_null())
+            .filter(user::Column::ArchivedAt.is_null())
+            .all(&self.db.orm)
+            .await?;
+
+        let mut sent = HashMap::<i32, HashMap<Kind, NaiveDateTime>>::new();
+        for n in notification::Entity::find().all(&self.db.orm).await? {
+            let last = sent
+                .entry(n.user)
+                .or_default()
+                .entry(n.kind)
+                .or_insert(n.created_at);
+            *last = n.created_at.max(*last);
+        }
+        let deposits = last_deposits(&self.db.orm).await?;
+
+        let now = Utc::now().naive_utc();
+        let mut count = 0;
+        for user in users {
+            let email = match &user.email {
+                Some(email) if !email.is_empty() => email.clone(),
+                _ => continue,
+            };
+            let kinds = due(
+                self.config,
+                &user,
+                &sent.remove(&user.id).unwrap_or_default(),
+                deposits.get(&user.id).copied(),
+                now,
+            );
+            for kind in kinds {
+                match self.send(&user, &email, kind).await {
+                    Ok(true) => count += 1,
+                    Ok(false) => {}
+                    // a single bad address must not keep everyone else waiting
+                    Err(err) => warn!("unable to notify user {}: {:?}", user.id, err),
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// sends all due notifications right away instead of waiting for the scheduler
+async fn run(
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<Config>,
+    _: Admin,
+) -> Result<Json<NotificationRunResponse>> {
+    let transport = transport(&config.notifications)?
+        .ok_or_else(|| AppError::InvalidInput("no smtp server configured".to_string()))?;
+    let notifier = Notifier {
+        db: &db,
+        config: &config.notifications,
+        transport: &transport,
+    };
+    let sent = notifier.run_due().await?;
+    Ok(Json(NotificationRunResponse { sent }))
+}
+
+/// background task sending reminders and statements, does nothing if no smtp
+/// server is configured
+pub async fn scheduler(db: Db, config: NotificationConfig) {
+    let transport = match transport(&config) {
+        Ok(Some(transport)) => transport,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("unable to set up smtp transport: {:?}", err);
+            return;
+        }
+    };
+    let notifier = Notifier {
+        db: &db,
+        config: &config,
+        transport: &transport,
+    };
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match notifier.run_due().await {
+            Ok(0) => {}
+            Ok(sent) => info!("sent {} notifications", sent),
+            Err(err) => warn!("unable to send notifications: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn reminders_are_not_repeated() {
+        let day = |m, d| NaiveDate::from_ymd(2022, m, d).and_hms(12, 0, 0);
+        let config = NotificationConfig {
+            low_balance: Some(500),
+            debt_reminder_days: Some(7),
+            monthly_statement: true,
+            ..Default::default()
+        };
+        let user = |balance| user::Model {
+            id: 1,
+            name: "alice".to_string(),
+            email: Some("alice@example.org".to_string()),
+            created_at: day(3, 1),
+            updated_at: day(3, 1),
+            balance,
+            active: true,
+            audit: false,
+            redirect: true,
+            avatar: None,
+            age_verified: false,
+            event: None,
+            merged_into: None,
+            archived_at: None,
+            notifications: true,
+        };
+        let now = day(5, 20);
+
+        assert_eq!(
+            due(&config, &user(-100), &HashMap::new(), None, now),
+            vec![Kind::LowBalance, Kind::Debt, Kind::Statement]
+        );
+        let sent = HashMap::from([
+            (Kind::LowBalance, day(5, 10)),
+            (Kind::Debt, day(5, 15)),
+            (Kind::Statement, day(5, 1)),
+        ]);
+        assert_eq!(
+            due(&config, &user(-100), &sent, Some(day(5, 1)), now),
+            vec![]
+        );
+        assert_eq!(
+            due(&config, &user(100), &sent, Some(day(5, 12)), day(5, 22)),
+            vec![Kind::LowBalance]
+        );
+        assert_eq!(
+            due(&config, &user(-100), &sent, None, day(6, 1)),
+            vec![Kind::Debt, Kind::Statement]
+        );
+    }
+
+    #[test]
+    fn templates_and_months() {
+        let values = [("name", "alice".to_string()), ("balance", amount(-250))];
+
+        assert_eq!(
+            render("Hi {name}, your balance is {balance} {unknown}", &values),
+            "Hi alice, your balance is -2.50 {unknown}"
+        );
+        assert_eq!(
+            last_month(NaiveDate::from_ymd(2022, 1, 15)),
+            (
+                NaiveDate::from_ymd(2021, 12, 1),
+                NaiveDate::from_ymd(2021, 12, 31)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_statements_are_not_composed_again() {
+        let config = NotificationConfig {
+            monthly_statement: true,
+            ..Default::default()
+        };
+        // nothing is sent, so the server is never contacted
+        let transport = Transport::builder_dangerous("127.0.0.1").port(9).build();
+        for db in crate::testing::backends("notifications").await {
+            user::ActiveModel {
+                name: Set("alice".to_owned()),
+                email: Set(Some("alice@example.org".to_owned())),
+                created_at: Set(Utc::now().naive_utc() - chrono::Duration::days(62)),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            let notifier = Notifier {
+                db: &db,
+                config: &config,
+                transport: &transport,
+            };
+
+            assert_eq!(notifier.run_due().await.unwrap(), 0);
+            assert_eq!(notifier.run_due().await.unwrap(), 0);
+            let kinds = notification::Entity::find()
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|n| n.kind)
+                .collect::<Vec<_>>();
+            assert_eq!(kinds, vec![Kind::Statement]);
+        }
+    }
+
+    #[tokio::test]
+    async fn last_deposit_per_user() {
+        // whole seconds, postgres only keeps microseconds
+        let now = Utc::now().naive_utc().date().and_hms(12, 0, 0);
+        let yesterday = now - chrono::Duration::days(1);
+        for db in crate::testing::backends("notifications_deposits").await {
+            let mut users = Vec::new();
+            for name in ["alice", "bob"] {
+                let user = user::ActiveModel {
+                    name: Set(name.to_owned()),
+                    ..Default::default()
+                };
+                users.push(user.insert(&db.orm).await.unwrap().id);
+            }
+            let bookings = [
+                (users[0], 500, yesterday),
+                (users[0], 200, now - chrono::Duration::days(10)),
+                (users[0], -100, now),
+                (users[1], -100, now),
+            ];
+            for (user, amount, created_at) in bookings {
+                transaction::ActiveModel {
+                    kind: Set(transaction::Kind::Deposit),
+                    user: Set(Some(user)),
+                    amount: Set(amount),
+                    created_at: Set(created_at),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            assert_eq!(
+                last_deposits(&db.orm).await.unwrap(),
+                HashMap::from([(users[0], yesterday)])
+            );
+        }
+    }
+}
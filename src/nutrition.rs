@@ -0,0 +1,268 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    ops::AddAssign,
+};
+
+use axum::{
+    extract::{Extension, Path, Query},
+    Json,
+};
+use chrono::{Duration, Local};
+use sea_orm::{entity::*, query::*, ConnectionTrait};
+
+use crate::{
+    config::NutritionConfig,
+    entity::{
+        product,
+        transaction::{self, Kind},
+        user,
+    },
+    models::{Bucket, Intake, IntakeEntry, IntakeWarning, Nutrient, NutritionResponse, StatsQuery},
+    repository::{Products, Transactions},
+    stats::{self, bucket_start, local_midnight, to_local},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// number of days reported if no range is requested
+const DEFAULT_DAYS: i64 = 28;
+
+impl AddAssign for Intake {
+    fn add_assign(&mut self, other: Self) {
+        self.caffeine += other.caffeine;
+        self.sugar += other.sugar;
+        self.alcohol += other.alcohol;
+        self.energy += other.energy;
+    }
+}
+
+/// intake caused by consuming one package of a product, products without a
+/// volume are not taken into account
+pub(crate) fn intake(product: &product::Model) -> Intake {
+    let volume = match product.volume {
+        Some(volume) => volume,
+        None => return Intake::default(),
+    };
+    let per_volume = |value: Option<i32>, divisor: i32| value.unwrap_or(0) * volume / divisor;
+
+    Intake {
+        caffeine: per_volume(product.caffeine, 100),
+        sugar: per_volume(product.sugar, 100),
+        // alcohol is stored as volume percent with two decimal places
+        alcohol: per_volume(product.alcohol, 1000),
+        energy: per_volume(product.energy, 100),
+    }
+}
+
+fn intake_series(
+    purchases: &[transaction::Model],
+    products: &HashMap<i32, Intake>,
+    bucket: Bucket,
+) -> Vec<IntakeEntry> {
+    let mut series = BTreeMap::new();
+    for t in purchases {
+        let intake = match t.product.and_then(|p| products.get(&p)) {
+            Some(intake) => *intake,
+            None => continue,
+        };
+        let start = bucket_start(to_local(t.created_at).date(), bucket);
+        *series.entry(start).or_insert_with(Intake::default) += intake;
+    }
+    series
+        .into_iter()
+        .map(|(start, intake)| IntakeEntry { start, intake })
+        .collect()
+}
+
+async fn product_intakes(db: &impl ConnectionTrait) -> Result<HashMap<i32, Intake>> {
+    Ok(product::Entity::find()
+        .all(db)
+        .await?
+        .iter()
+        .map(|p| (p.id, intake(p)))
+        .collect())
+}
+
+/// intake of a user per day and week
+pub(crate) async fn get(
+    Path(id): Path<i32>,
+    Query(mut query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<NutritionResponse>> {
+    user::Entity::find_by_id(id)
+        .one(&db.orm)
+        .await?
+        .ok_or(AppError::NotFount)?;
+
+    if query.from.is_none() {
+        let to = query.to.unwrap_or_else(|| Local::today().naive_local());
+        query.from = Some(to - Duration::days(DEFAULT_DAYS - 1));
+    }
+
+    let products = product_intakes(&db.orm).await?;
+    let purchases = stats::transactions(Kind::Purchase, &query)
+        .filter(transaction::Column::User.eq(id))
+        .all(&db.orm)
+        .await?;
+
+    Ok(Json(NutritionResponse {
+        daily: intake_series(&purchases, &products, Bucket::Day),
+        weekly: intake_series(&purchases, &products, Bucket::Week),
+    }))
+}
+
+/// a warning if the intake of the day reached the limit with the last purchase
+fn limit_crossed(
+    nutrient: Nutrient,
+    limit: i32,
+    before: i32,
+    intake: i32,
+) -> Option<IntakeWarning> {
+    (before < limit && intake >= limit).then_some(IntakeWarning {
+        nutrient,
+        intake,
+        limit,
+    })
+}
+
+/// Warnings for every configured daily limit the purchase, which is already
+/// booked, made the user exceed today. Later purchases of the day don't warn
+/// again.
+pub(crate) async fn warnings(
+    config: &NutritionConfig,
+    products: &Products,
+    transactions: &Transactions,
+    purchase: &transaction::Model,
+) -> Result<Vec<IntakeWarning>> {
+    let (limit, user) = match (config.caffeine_warning, purchase.user) {
+        (Some(limit), Some(user)) => (limit, user),
+        _ => return Ok(Vec::new()),
+    };
+
+    let today = local_midnight(Local::today().naive_local());
+    let purchases = transactions.purchases(user, today).await?;
+    // only the products bought today matter
+    let mut intakes = HashMap::new();
+    for id in purchases.iter().filter_map(|t| t.product) {
+        if let Entry::Vacant(entry) = intakes.entry(id) {
+            entry.insert(intake(&products.find(id).await?));
+        }
+    }
+    let intake = intake_series(&purchases, &intakes, Bucket::Day)
+        .into_iter()
+        .map(|entry| entry.intake.caffeine)
+        .sum::<i32>();
+    let purchased = purchase
+        .product
+        .and_then(|p| intakes.get(&p))
+        .map_or(0, |intake| intake.caffeine);
+
+    Ok(
+        limit_crossed(Nutrient::Caffeine, limit, intake - purchased, intake)
+            .into_iter()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn intake_scales_with_volume() {
+        let mate = product::Model {
+            id: 1,
+            name: "Club-Mate".to_string(),
+            caffeine: Some(20),
+            alcohol: Some(0),
+            energy: Some(84),
+            sugar: Some(50),
+            volume: Some(500),
+            price: 150,
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+            active: true,
+            image: None,
+            age_restricted: false,
+            stock: None,
+        };
+        let beer = product::Model {
+            name: "Beer".to_string(),
+            caffeine: None,
+            alcohol: Some(480),
+            energy: None,
+            sugar: None,
+            volume: Some(330),
+            ..mate.clone()
+        };
+
+        assert_eq!(
+            intake(&mate),
+            Intake {
+                caffeine: 100,
+                sugar: 250,
+                alcohol: 0,
+                energy: 420,
+            }
+        );
+        assert_eq!(intake(&beer).alcohol, 158);
+        assert!(limit_crossed(Nutrient::Caffeine, 400, 300, 400).is_some());
+        assert!(limit_crossed(Nutrient::Caffeine, 400, 400, 500).is_none());
+        assert!(limit_crossed(Nutrient::Caffeine, 400, 200, 300).is_none());
+        assert_eq!(
+            intake(&product::Model {
+                volume: None,
+                ..mate
+            }),
+            Intake::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_purchase_reaching_the_limit_warns() {
+        let repositories = crate::repository::Repositories::memory();
+        let mate = crate::models::ProductCreateRequest {
+            name: "Club-Mate".to_string(),
+            caffeine: Some(20),
+            alcohol: None,
+            energy: None,
+            sugar: None,
+            volume: Some(500),
+            price: None,
+            active: None,
+            image: None,
+            age_restricted: None,
+            stock: None,
+        };
+        let mate = repositories.products.create(mate).await.unwrap();
+        let user = crate::models::UserCreateRequest {
+            name: "alice".to_string(),
+            ..Default::default()
+        };
+        let user = repositories.users.create(user).await.unwrap();
+        let config = NutritionConfig {
+            caffeine_warning: Some(300),
+        };
+
+        let mut warned = Vec::new();
+        for _ in 0..4 {
+            let (_, _, purchase) = repositories
+                .transactions
+                .buy(user.id, mate.id)
+                .await
+                .unwrap();
+            let warnings = warnings(
+                &config,
+                &repositories.products,
+                &repositories.transactions,
+                &purchase,
+            )
+            .await
+            .unwrap();
+            warned.push(warnings.iter().map(|w| w.intake).collect::<Vec<_>>());
+        }
+        assert_eq!(warned, vec![vec![], vec![], vec![300], vec![]]);
+    }
+}
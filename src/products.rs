@@ -4,27 +4,29 @@ use axum::{
     routing, Json, Router,
 };
 
-use std::convert::TryInto;
-
-use sea_orm::entity::*;
+use sea_orm::{entity::*, ConnectionTrait};
 
 use crate::{
+    auth::Admin,
     config::Config,
-    entity::product::{self, Entity as ProductModel},
-    models::{Product, ProductCreateRequest, ProductEditRequest},
-    storage::Db,
+    entity::{product, webhook_event},
+    live::Live,
+    models::{LiveEvent, Product, ProductCreateRequest, ProductEditRequest},
+    repository::{Products, Transactions},
     utils::{AppError, Result},
+    webhooks,
 };
 
 pub fn router() -> Router {
     Router::new()
         .route("/", routing::get(get_all).post(create))
         .route("/:id", routing::get(get).delete(delete).patch(edit))
+        .route("/:id/sell", routing::post(sell))
 }
 
-async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<Product>>> {
-    let products = ProductModel::find()
-        .all(&db.orm)
+async fn get_all(Extension(products): Extension<Products>) -> Result<Json<Vec<Product>>> {
+    let products = products
+        .all()
         .await?
         .into_iter()
         .map(Into::into)
@@ -35,84 +37,205 @@ async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<Product>>> {
 
 async fn create(
     Json(product): Json<ProductCreateRequest>,
-    Extension(db): Extension<Db>,
+    Extension(products): Extension<Products>,
     Extension(config): Extension<Config>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
 ) -> Result<(StatusCode, Json<Product>)> {
-    let product = product::ActiveModel {
-        name: Set(product.name),
-        caffeine: Set(product.caffeine.or(config.default_product.caffeine)),
-        alcohol: Set(product.alcohol.or(config.default_product.alcohol)),
-        energy: Set(product.energy.or(config.default_product.energy)),
-        sugar: Set(product.sugar.or(config.default_product.sugar)),
-        price: Set(product.price.unwrap_or(config.default_product.price)),
-        active: Set(product.active.unwrap_or(config.default_product.active)),
-        ..Default::default()
+    // restricted products are derived from the alcohol, only admins may decide otherwise
+    if product.age_restricted.is_some() && admin.is_none() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let defaults = config.default_product;
+    let alcohol = product.alcohol.or(defaults.alcohol);
+    let product = ProductCreateRequest {
+        caffeine: product.caffeine.or(defaults.caffeine),
+        alcohol,
+        energy: product.energy.or(defaults.energy),
+        sugar: product.sugar.or(defaults.sugar),
+        price: Some(product.price.unwrap_or(defaults.price)),
+        active: Some(product.active.unwrap_or(defaults.active)),
+        age_restricted: Some(
+            product
+                .age_restricted
+                .unwrap_or_else(|| alcohol.unwrap_or(0) > 0),
+        ),
+        ..product
     };
 
-    let product = product.save(&db.orm).await?.try_into()?;
+    let product: Product = products.create(product).await?.into();
+    live.publish([LiveEvent::Product(product.clone())]);
     Ok((StatusCode::CREATED, Json(product)))
 }
 
-async fn delete(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<&'static str> {
-    ProductModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?
-        .into_active_model()
-        .delete(&db.orm)
-        .await?;
+async fn delete(
+    Path(id): Path<i32>,
+    Extension(products): Extension<Products>,
+    Extension(live): Extension<Live>,
+) -> Result<&'static str> {
+    products.delete(id).await?;
+    live.publish([LiveEvent::ProductDeleted(id)]);
     Ok("product deleted")
 }
 
-async fn get(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<Json<Product>> {
-    let product = ProductModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?
-        .into();
-    Ok(Json(product))
+async fn get(
+    Path(id): Path<i32>,
+    Extension(products): Extension<Products>,
+) -> Result<Json<Product>> {
+    Ok(Json(products.find(id).await?.into()))
 }
 
 async fn edit(
     Path(id): Path<i32>,
     Json(body): Json<ProductEditRequest>,
-    Extension(db): Extension<Db>,
+    Extension(products): Extension<Products>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
 ) -> Result<Json<Product>> {
-    let mut product = ProductModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?
-        .into_active_model();
-
-    product.name = body.name.map(ActiveValue::set).unwrap_or(product.name);
-    product.caffeine = body
-        .caffeine
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(product.caffeine);
-    product.alcohol = body
-        .alcohol
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(product.alcohol);
-    product.energy = body
-        .energy
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(product.energy);
-    product.sugar = body
-        .sugar
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(product.sugar);
-    product.price = body.price.map(ActiveValue::set).unwrap_or(product.price);
-    product.active = body.active.map(ActiveValue::set).unwrap_or(product.active);
-    product.image = body
-        .image
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(product.image);
-
-    let product = product.save(&db.orm).await?.try_into()?;
+    if (body.age_restricted.is_some() || body.alcohol.is_some()) && admin.is_none() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let product: Product = products.edit(id, body).await?.into();
+    live.publish([LiveEvent::Product(product.clone())]);
     Ok(Json(product))
 }
+
+/// Refuses anonymous sales of nothing, of inactive products and of age
+/// restricted products without a checked age. The stock is not checked, it
+/// only counts what should be left and may go negative if it was not refilled.
+pub(crate) fn ensure_sellable(
+    product: &product::Model,
+    count: i32,
+    age_verified: bool,
+) -> Result<()> {
+    if count < 1 {
+        return Err(AppError::InvalidInput(
+            "at least one item has to be sold".to_owned(),
+        ));
+    }
+    if !product.active {
+        return Err(AppError::Conflict);
+    }
+    if product.age_restricted && !age_verified {
+        return Err(AppError::AgeRestricted);
+    }
+    Ok(())
+}
+
+/// removes sold items from the stock, if the stock of the product is tracked
+pub(crate) async fn take_from_stock(
+    db: &impl ConnectionTrait,
+    product: product::Model,
+    count: i32,
+) -> Result<product::Model> {
+    match product.stock {
+        Some(stock) => {
+            let mut product = product.into_active_model();
+            product.stock = Set(Some(stock - count));
+            let product = product.update(db).await?;
+            if stock > 0 && stock - count <= 0 {
+                let data = Product::from(product.clone());
+                webhooks::emit(db, webhook_event::Kind::OutOfStock, &data).await?;
+            }
+            Ok(product)
+        }
+        None => Ok(product),
+    }
+}
+
+/// Anonymous sale paid in cash, the body optionally contains the number of
+/// sold items. Age restricted products can only be sold by an admin, who has
+/// to check the age of the buyer. Inactive products can't be sold, selling
+/// more than the stock is allowed and leaves a negative stock.
+async fn sell(
+    Path(id): Path<i32>,
+    body: String,
+    Extension(transactions): Extension<Transactions>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
+) -> Result<Json<Product>> {
+    let count = match body.trim() {
+        "" => 1,
+        count => i32::from(count.parse::<u16>()?),
+    };
+    if count == 0 {
+        return Err(AppError::InvalidInput(
+            "at least one item has to be sold".to_owned(),
+        ));
+    }
+    let (product, purchases) = transactions.sell(id, count, admin.is_some()).await?;
+
+    let product = Product::from(product);
+    let mut changes = purchases
+        .into_iter()
+        .map(|t| LiveEvent::Transaction(t.into()))
+        .collect::<Vec<_>>();
+    changes.push(LiveEvent::Product(product.clone()));
+    live.publish(changes);
+    Ok(Json(product))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, send, send_admin};
+
+    #[tokio::test]
+    async fn products_use_defaults_and_track_stock() {
+        for (repositories, db) in testing::repositories("products").await {
+            let app = testing::app(router(), repositories, db);
+
+            let body = r#"{"name": "Beer", "alcohol": 480, "stock": 5}"#;
+            let (status, beer) = send(&app, "POST", "/", body).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(
+                (&beer["price"], &beer["age_restricted"], &beer["active"]),
+                (&json!(150), &json!(true), &json!(true))
+            );
+            let id = beer["id"].as_i64().unwrap();
+
+            let (_, beer) = send(&app, "PATCH", &format!("/{}", id), r#"{"price": 200}"#).await;
+            assert_eq!((&beer["price"], &beer["stock"]), (&json!(200), &json!(5)));
+            // only admins may lift the age restriction
+            for body in [r#"{"age_restricted": false}"#, r#"{"alcohol": 0}"#] {
+                let (status, _) = send(&app, "PATCH", &format!("/{}", id), body).await;
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+            }
+            let body = r#"{"name": "Radler", "alcohol": 250, "age_restricted": false}"#;
+            let (status, _) = send(&app, "POST", "/", body).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            let (status, radler) = send_admin(&app, "POST", "/", body).await;
+            assert_eq!(
+                (status, &radler["age_restricted"]),
+                (StatusCode::CREATED, &json!(false))
+            );
+            let sell = format!("/{}/sell", id);
+            let (status, _) = send(&app, "POST", &sell, "3").await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            let (_, beer) = send_admin(&app, "POST", &sell, "3").await;
+            assert_eq!(beer["stock"], json!(2));
+            let (status, _) = send_admin(&app, "POST", &sell, "lots").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let (status, _) = send_admin(&app, "POST", &sell, "0").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            // the stock only counts what should be left, it may go negative
+            let (_, beer) = send_admin(&app, "POST", &sell, "3").await;
+            assert_eq!(beer["stock"], json!(-1));
+            let body = r#"{"active": false}"#;
+            send_admin(&app, "PATCH", &format!("/{}", id), body).await;
+            let (status, _) = send_admin(&app, "POST", &sell, "").await;
+            assert_eq!(status, StatusCode::CONFLICT);
+
+            let (_, mate) = send(&app, "POST", "/", r#"{"name": "Club-Mate"}"#).await;
+            let (status, _) = send(&app, "DELETE", &format!("/{}", mate["id"]), "").await;
+            assert_eq!(status, StatusCode::OK);
+            let (_, products) = send(&app, "GET", "/", "").await;
+            assert_eq!(products.as_array().map(Vec::len), Some(2));
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashSet, time::Duration};
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use chrono::{Datelike, Local, NaiveDate};
+use sea_orm::{entity::*, query::*, ConnectionTrait, TransactionTrait};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Admin,
+    entity::{
+        group_account, recurring_booking,
+        recurring_charge::{self, Interval},
+        recurring_charge_user,
+        transaction::{self, Kind},
+        user,
+    },
+    live::Live,
+    models::{LiveEvent, RecurringCharge, RecurringChargeRequest, RecurringRunResponse},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// how often the scheduler looks for periods that are due
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", routing::get(get_all).post(create))
+        .route("/run", routing::post(run))
+        .route("/:id", routing::delete(deactivate))
+}
+
+fn add_months(date: NaiveDate, months: u32) -> NaiveDate {
+    let month = date.month0() + months;
+    let (year, month) = (date.year() + (month / 12) as i32, month % 12 + 1);
+    // the last day of the month is the day before the first of the next one
+    let last_day = match month {
+        12 => NaiveDate::from_ymd(year + 1, 1, 1),
+        _ => NaiveDate::from_ymd(year, month + 1, 1),
+    }
+    .pred()
+    .day();
+    NaiveDate::from_ymd(year, month, date.day().min(last_day))
+}
+
+/// start of every period of a charge up to and including `today`
+fn periods(starts_on: NaiveDate, interval: Interval, today: NaiveDate) -> Vec<NaiveDate> {
+    (0..)
+        .map(|n| match interval {
+            Interval::Daily => starts_on + chrono::Duration::days(n as i64),
+            Interval::Weekly => starts_on + chrono::Duration::weeks(n as i64),
+            Interval::Monthly => add_months(starts_on, n),
+        })
+        .take_while(|period| *period <= today)
+        .collect()
+}
+
+async fn with_users(
+    db: &impl ConnectionTrait,
+    charge: recurring_charge::Model,
+) -> Result<RecurringCharge> {
+    let users = recurring_charge_user::Entity::find()
+        .filter(recurring_charge_user::Column::Charge.eq(charge.id))
+        .order_by_asc(recurring_charge_user::Column::User)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|u| u.user)
+        .collect();
+
+    Ok(RecurringCharge {
+        id: charge.id,
+        name: charge.name,
+        amount: charge.amount,
+        interval: charge.interval,
+        starts_on: charge.starts_on,
+        users,
+        group_account: charge.group_account,
+        active: charge.active,
+        created_at: chrono::DateTime::from_utc(charge.created_at, chrono::Utc),
+    })
+}
+
+async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<RecurringCharge>>> {
+    let mut charges = Vec::new();
+    for charge in recurring_charge::Entity::find().all(&db.orm).await? {
+        charges.push(with_users(&db.orm, charge).await?);
+    }
+    Ok(Json(charges))
+}
+
+async fn create(
+    Json(request): Json<RecurringChargeRequest>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<(StatusCode, Json<RecurringCharge>)> {
+    if request.amount <= 0 {
+        return Err(AppError::InvalidInput(
+            "amount must be positive".to_string(),
+        ));
+    }
+    if request.users.is_empty() && request.group_account.is_none() {
+        return Err(AppError::InvalidInput(
+            "no users or group to charge".to_string(),
+        ));
+    }
+
+    let charge = db
+        .orm
+        .transaction::<_, RecurringCharge, AppError>(|txn| {
+            Box::pin(async move {
+                if let Some(group) = request.group_account {
+                    group_account::Entity::find_by_id(group)
+                        .one(txn)
+                        .await?
+                        .ok_or(AppError::NotFount)?;
+                }
+                let charge = recurring_charge::ActiveModel {
+                    name: Set(request.name),
+                    amount: Set(request.amount),
+                    interval: Set(request.interval),
+                    starts_on: Set(request
+                        .starts_on
+                        .unwrap_or_else(|| Local::today().naive_local())),
+                    group_account: Set(request.group_account),
+                    active: Set(true),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+
+                for user in request.users.into_iter().collect::<HashSet<_>>() {
+                    user::Entity::find_by_id(user)
+                        .one(txn)
+                        .await?
+                        .ok_or(AppError::NotFount)?;
+                    recurring_charge_user::ActiveModel {
+                        charge: Set(charge.id),
+                        user: Set(user),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+                }
+
+                with_users(txn, charge).await
+            })
+        })
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(charge)))
+}
+
+/// stops booking a charge, periods booked so far are kept
+async fn deactivate(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Db>,
+    _: Admin,
+) -> Result<Json<RecurringCharge>> {
+    let mut charge = recurring_charge::Entity::find_by_id(id)
+        .one(&db.orm)
+        .await?
+        .ok_or(AppError::NotFount)?
+        .into_active_model();
+    charge.active = Set(false);
+    let charge = charge.update(&db.orm).await?;
+
+    Ok(Json(with_users(&db.orm, charge).await?))
+}
+
+/// Merged, archived and deactivated users are skipped, the period is not
+/// booked for them later on either.
+fn is_charged(user: &user::Model) -> bool {
+    user.merged_into.is_none() && user.archived_at.is_none() && user.active
+}
+
+/// Books a single period of a charge for all its targets. Returns the changes
+/// to publish, `None` if the period was booked before.
+async fn book(
+    db: &Db,
+    charge: recurring_charge::Model,
+    period: NaiveDate,
+) -> Result<Option<Vec<LiveEvent>>> {
+    db.orm
+        .transaction::<_, Option<Vec<LiveEvent>>, AppError>(|txn| {
+            Box::pin(async move {
+                let booked = recurring_booking::Entity::find()
+                    .filter(recurring_booking::Column::Charge.eq(charge.id))
+                    .filter(recurring_booking::Column::Period.eq(period))
+                    .one(txn)
+                    .await?;
+                if booked.is_some() {
+                    return Ok(None);
+                }
+                recurring_booking::ActiveModel {
+                    charge: Set(charge.id),
+                    period: Set(period),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+
+                let reference = format!("{} {}", charge.name, period);
+                let users = recurring_charge_user::Entity::find()
+                    .filter(recurring_charge_user::Column::Charge.eq(charge.id))
+                    .all(txn)
+                    .await?;
+                let mut changes = Vec::new();
+                for target in users {
+                    let user = match user::Entity::find_by_id(target.user).one(txn).await? {
+                        Some(user) if is_charged(&user) => user,
+                        _ => continue,
+                    };
+                    let balance = user.balance;
+                    let mut user = user.into_active_model();
+                    user.balance = Set(balance - charge.amount);
+                    let user = user.update(txn).await?;
+
+                    let spending = transaction::ActiveModel {
+                        kind: Set(Kind::Spend),
+                        user: Set(Some(target.user)),
+                        amount: Set(-charge.amount),
+                        reference: Set(Some(reference.clone())),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+                    changes.push(LiveEvent::User(user.into()));
+                    changes.push(LiveEvent::Transaction(spending.into()));
+                }
+
+                if let Some(group) = charge.group_account {
+                    if let Some(account) = group_account::Entity::find_by_id(group).one(txn).await?
+                    {
+                        let balance = account.balance;
+                        let mut account = account.into_active_model();
+                        account.balance = Set(balance - charge.amount);
+                        account.update(txn).await?;
+
+                        let spending = transaction::ActiveModel {
+                            kind: Set(Kind::Spend),
+                            group_account: Set(Some(group)),
+                            amount: Set(-charge.amount),
+                            reference: Set(Some(reference)),
+                            ..Default::default()
+                        }
+                        .insert(txn)
+                        .await?;
+                        changes.push(LiveEvent::Transaction(spending.into()));
+                    }
+                }
+
+                Ok(Some(changes))
+            })
+        })
+        .await
+        .map_err(Into::into)
+}
+
+/// Books every period of every active charge that is due and was not booked
+/// yet. Periods missed while the server was down are booked as well.
+async fn run_due(db: &Db, live: &Live) -> Result<i32> {
+    let today = Local::today().naive_local();
+    let charges = recurring_charge::Entity::find()
+        .filter(recurring_charge::Column::Active.eq(true))
+        .all(&db.orm)
+        .await?;
+
+    let mut booked = 0;
+    for charge in charges {
+        let done = recurring_booking::Entity::find()
+            .filter(recurring_booking::Column::Charge.eq(charge.id))
+            .all(&db.orm)
+            .await?
+            .into_iter()
+            .map(|b| b.period)
+            .collect::<HashSet<_>>();
+        for period in periods(charge.starts_on, charge.interval, today) {
+            if done.contains(&period) {
+                continue;
+            }
+            if let Some(changes) = book(db, charge.clone(), period).await? {
+                live.publish(changes);
+                booked += 1;
+            }
+        }
+    }
+    Ok(booked)
+}
+
+/// books all due periods right away instead of waiting for the scheduler
+async fn run(
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    _: Admin,
+) -> Result<Json<RecurringRunResponse>> {
+    let booked = run_due(&db, &live).await?;
+    Ok(Json(RecurringRunResponse { booked }))
+}
+
+/// background task booking recurring charges, starting right away to catch up
+/// on periods missed while the server was not running
+pub async fn scheduler(db: Db, live: Live) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match run_due(&db, &live).await {
+            Ok(0) => {}
+            Ok(booked) => info!("booked {} periods of recurring charges", booked),
+            Err(err) => warn!("unable to book recurring charges: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn periods_up_to_today() {
+        let day = |m, d| NaiveDate::from_ymd(2022, m, d);
+
+        assert_eq!(
+            periods(day(1, 31), Interval::Monthly, day(5, 30)),
+            vec![day(1, 31), day(2, 28), day(3, 31), day(4, 30)]
+        );
+        assert_eq!(
+            periods(day(3, 1), Interval::Weekly, day(3, 15)),
+            vec![day(3, 1), day(3, 8), day(3, 15)]
+        );
+        assert_eq!(periods(day(3, 1), Interval::Daily, day(2, 28)), vec![]);
+        assert_eq!(
+            add_months(NaiveDate::from_ymd(2022, 11, 30), 3),
+            NaiveDate::from_ymd(2023, 2, 28)
+        );
+    }
+
+    #[tokio::test]
+    async fn only_active_users_are_charged() {
+        for db in crate::testing::backends("recurring").await {
+            let mut users = Vec::new();
+            for (name, active, archived) in [
+                ("active", true, false),
+                ("inactive", false, false),
+                ("archived", false, true),
+            ] {
+                let user = user::ActiveModel {
+                    name: Set(name.to_owned()),
+                    balance: Set(0),
+                    active: Set(active),
+                    archived_at: Set(archived.then(|| chrono::Utc::now().naive_utc())),
+                    ..Default::default()
+                };
+                users.push(user.insert(&db.orm).await.unwrap().id);
+            }
+            let charge = recurring_charge::ActiveModel {
+                name: Set("rent".to_owned()),
+                amount: Set(500),
+                interval: Set(Interval::Monthly),
+                starts_on: Set(Local::today().naive_local()),
+                active: Set(true),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            for &user in &users {
+                recurring_charge_user::ActiveModel {
+                    charge: Set(charge.id),
+                    user: Set(user),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            let live = Live::default();
+            let mut events = live.subscribe();
+            assert_eq!(run_due(&db, &live).await.unwrap(), 1);
+            assert!(matches!(events.try_recv(), Ok(LiveEvent::User(u)) if u.id == users[0]));
+            assert!(matches!(events.try_recv(), Ok(LiveEvent::Transaction(t)) if t.amount == -500));
+            assert!(events.try_recv().is_err());
+            let balances = user::Entity::find()
+                .order_by_asc(user::Column::Id)
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.balance)
+                .collect::<Vec<_>>();
+            assert_eq!(balances, vec![-500, 0, 0]);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use axum::{async_trait, extract::Extension, Router};
+use chrono::NaiveDateTime;
+
+use crate::{
+    entity::{product, transaction, user},
+    models::{ProductCreateRequest, ProductEditRequest, UserCreateRequest, UserEditRequest},
+    storage::Db,
+    utils::Result,
+};
+
+mod database;
+#[cfg(test)]
+mod memory;
+
+pub(crate) use database::Database;
+#[cfg(test)]
+pub(crate) use memory::Memory;
+
+/// users as seen by the handlers, see [`Repositories`]
+pub(crate) type Users = Arc<dyn UserRepository>;
+pub(crate) type Products = Arc<dyn ProductRepository>;
+pub(crate) type Transactions = Arc<dyn TransactionRepository>;
+
+#[async_trait]
+pub(crate) trait UserRepository: Send + Sync {
+    async fn all(&self) -> Result<Vec<user::Model>>;
+
+    /// fails with [`AppError::NotFount`](crate::utils::AppError::NotFount)
+    async fn find(&self, id: i32) -> Result<user::Model>;
+
+    /// fails with a conflict if the name is taken
+    async fn create(&self, user: UserCreateRequest) -> Result<user::Model>;
+
+    /// changes every field set in the request
+    async fn edit(&self, id: i32, edit: UserEditRequest) -> Result<user::Model>;
+
+    async fn delete(&self, id: i32) -> Result<()>;
+}
+
+#[async_trait]
+pub(crate) trait ProductRepository: Send + Sync {
+    async fn all(&self) -> Result<Vec<product::Model>>;
+
+    async fn find(&self, id: i32) -> Result<product::Model>;
+
+    /// The defaults of the config have to be applied by the caller, missing
+    /// prices are 0, products are active and not age restricted.
+    async fn create(&self, product: ProductCreateRequest) -> Result<product::Model>;
+
+    async fn edit(&self, id: i32, edit: ProductEditRequest) -> Result<product::Model>;
+
+    async fn delete(&self, id: i32) -> Result<()>;
+}
+
+/// Bookings changing balances or stock together with the ledger, each one
+/// either succeeds as a whole or changes nothing.
+#[async_trait]
+pub(crate) trait TransactionRepository: Send + Sync {
+    /// deposit or spend money, `amount` is the change of the balance
+    async fn book(
+        &self,
+        user: i32,
+        kind: transaction::Kind,
+        amount: i32,
+    ) -> Result<(user::Model, transaction::Model)>;
+
+    /// the user buys one item of the product with their balance
+    async fn buy(
+        &self,
+        user: i32,
+        product: i32,
+    ) -> Result<(user::Model, product::Model, transaction::Model)>;
+
+    /// Anonymous sale of `count` items paid in cash. Inactive products, and
+    /// age restricted products unless the age of the buyer was checked, are
+    /// refused. The stock may go negative.
+    async fn sell(
+        &self,
+        product: i32,
+        count: i32,
+        age_verified: bool,
+    ) -> Result<(product::Model, Vec<transaction::Model>)>;
+
+    /// moves `amount` from the sender to the receiver
+    async fn transfer(
+        &self,
+        sender: i32,
+        receiver: i32,
+        amount: i32,
+    ) -> Result<(user::Model, user::Model, Vec<transaction::Model>)>;
+
+    /// purchases of the user made at or after `since` (utc)
+    async fn purchases(&self, user: i32, since: NaiveDateTime) -> Result<Vec<transaction::Model>>;
+}
+
+/// The repositories handlers get as [`Extension`], either all backed by the
+/// database or all kept in memory for tests.
+#[derive(Clone)]
+pub(crate) struct Repositories {
+    pub users: Users,
+    pub products: Products,
+    pub transactions: Transactions,
+}
+
+impl Repositories {
+    pub fn database(db: Db) -> Self {
+        let database = Arc::new(Database::new(db));
+        Self {
+            users: database.clone(),
+            products: database.clone(),
+            transactions: database,
+        }
+    }
+
+    /// empty repositories, nothing is persisted and no webhooks are sent
+    #[cfg(test)]
+    pub fn memory() -> Self {
+        let memory = Arc::new(Memory::default());
+        Self {
+            users: memory.clone(),
+            products: memory.clone(),
+            transactions: memory,
+        }
+    }
+
+    /// makes the repositories available to all routes of the router
+    pub fn layer(self, router: Router) -> Router {
+        router
+            .layer(Extension(self.users))
+            .layer(Extension(self.products))
+            .layer(Extension(self.transactions))
+    }
+}
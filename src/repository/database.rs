@@ -0,0 +1,360 @@
+use axum::async_trait;
+use chrono::NaiveDateTime;
+use sea_orm::{entity::*, query::*, TransactionTrait};
+
+use super::{ProductRepository, TransactionRepository, UserRepository};
+use crate::{
+    entity::{
+        product,
+        transaction::{self, Kind},
+        user, webhook_event,
+    },
+    models::{
+        LedgerEntry, ProductCreateRequest, ProductEditRequest, UserCreateRequest, UserEditRequest,
+        WebhookTransfer,
+    },
+    products,
+    storage::Db,
+    user::{ensure_bookable, ensure_may_buy, insert},
+    utils::{AppError, Result},
+    webhooks,
+};
+
+/// repositories backed by sea-orm, webhooks are queued together with changes
+#[derive(Debug, Clone)]
+pub(crate) struct Database {
+    db: Db,
+}
+
+impl Database {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+}
+
+async fn find_user(db: &impl ConnectionTrait, id: i32) -> Result<user::Model> {
+    user::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFount)
+}
+
+async fn find_product(db: &impl ConnectionTrait, id: i32) -> Result<product::Model> {
+    product::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFount)
+}
+
+#[async_trait]
+impl UserRepository for Database {
+    async fn all(&self) -> Result<Vec<user::Model>> {
+        Ok(user::Entity::find().all(&self.db.orm).await?)
+    }
+
+    async fn find(&self, id: i32) -> Result<user::Model> {
+        find_user(&self.db.orm, id).await
+    }
+
+    async fn create(&self, user: UserCreateRequest) -> Result<user::Model> {
+        insert(&self.db.orm, user, None, None).await
+    }
+
+    async fn edit(&self, id: i32, body: UserEditRequest) -> Result<user::Model> {
+        let mut user = find_user(&self.db.orm, id).await?.into_active_model();
+
+        user.name = body.name.map(ActiveValue::set).unwrap_or(user.name);
+        user.email = body
+            .email
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(user.email);
+        user.balance = body.balance.map(ActiveValue::set).unwrap_or(user.balance);
+        user.active = body.active.map(ActiveValue::set).unwrap_or(user.active);
+        user.audit = body.audit.map(ActiveValue::set).unwrap_or(user.audit);
+        user.redirect = body.redirect.map(ActiveValue::set).unwrap_or(user.redirect);
+        user.avatar = body
+            .avatar
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(user.avatar);
+        user.age_verified = body
+            .age_verified
+            .map(ActiveValue::set)
+            .unwrap_or(user.age_verified);
+        user.notifications = body
+            .notifications
+            .map(ActiveValue::set)
+            .unwrap_or(user.notifications);
+
+        Ok(user.update(&self.db.orm).await?)
+    }
+
+    async fn delete(&self, id: i32) -> Result<()> {
+        find_user(&self.db.orm, id)
+            .await?
+            .into_active_model()
+            .delete(&self.db.orm)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProductRepository for Database {
+    async fn all(&self) -> Result<Vec<product::Model>> {
+        Ok(product::Entity::find().all(&self.db.orm).await?)
+    }
+
+    async fn find(&self, id: i32) -> Result<product::Model> {
+        find_product(&self.db.orm, id).await
+    }
+
+    async fn create(&self, product: ProductCreateRequest) -> Result<product::Model> {
+        let product = product::ActiveModel {
+            name: Set(product.name),
+            caffeine: Set(product.caffeine),
+            alcohol: Set(product.alcohol),
+            energy: Set(product.energy),
+            sugar: Set(product.sugar),
+            volume: Set(product.volume),
+            price: Set(product.price.unwrap_or_default()),
+            active: Set(product.active.unwrap_or(true)),
+            age_restricted: Set(product.age_restricted.unwrap_or(false)),
+            stock: Set(product.stock),
+            ..Default::default()
+        };
+        Ok(product.insert(&self.db.orm).await?)
+    }
+
+    async fn edit(&self, id: i32, body: ProductEditRequest) -> Result<product::Model> {
+        let mut product = find_product(&self.db.orm, id).await?.into_active_model();
+
+        product.name = body.name.map(ActiveValue::set).unwrap_or(product.name);
+        product.caffeine = body
+            .caffeine
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.caffeine);
+        product.alcohol = body
+            .alcohol
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.alcohol);
+        product.energy = body
+            .energy
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.energy);
+        product.sugar = body
+            .sugar
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.sugar);
+        product.volume = body
+            .volume
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.volume);
+        product.price = body.price.map(ActiveValue::set).unwrap_or(product.price);
+        product.active = body.active.map(ActiveValue::set).unwrap_or(product.active);
+        product.image = body
+            .image
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.image);
+        product.age_restricted = body
+            .age_restricted
+            .map(ActiveValue::set)
+            .unwrap_or(product.age_restricted);
+        product.stock = body
+            .stock
+            .map(Option::Some)
+            .map(ActiveValue::set)
+            .unwrap_or(product.stock);
+
+        Ok(product.update(&self.db.orm).await?)
+    }
+
+    async fn delete(&self, id: i32) -> Result<()> {
+        find_product(&self.db.orm, id)
+            .await?
+            .into_active_model()
+            .delete(&self.db.orm)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransactionRepository for Database {
+    async fn book(
+        &self,
+        user: i32,
+        kind: Kind,
+        amount: i32,
+    ) -> Result<(user::Model, transaction::Model)> {
+        Ok(self
+            .db
+            .orm
+            .transaction::<_, _, AppError>(|txn| {
+                Box::pin(async move {
+                    let user = find_user(txn, user).await?;
+                    ensure_bookable(&user)?;
+                    let balance = user.balance;
+                    let id = user.id;
+                    let mut user = user.into_active_model();
+                    user.balance = Set(balance + amount);
+
+                    let transaction = transaction::ActiveModel {
+                        kind: Set(kind),
+                        user: Set(Some(id)),
+                        amount: Set(amount),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+                    if kind == Kind::Deposit {
+                        let data = LedgerEntry::from(transaction.clone());
+                        webhooks::emit(txn, webhook_event::Kind::Deposit, &data).await?;
+                    }
+
+                    Ok((user.update(txn).await?, transaction))
+                })
+            })
+            .await?)
+    }
+
+    async fn buy(
+        &self,
+        user: i32,
+        product: i32,
+    ) -> Result<(user::Model, product::Model, transaction::Model)> {
+        Ok(self
+            .db
+            .orm
+            .transaction::<_, _, AppError>(|txn| {
+                Box::pin(async move {
+                    let product = find_product(txn, product).await?;
+                    let user = find_user(txn, user).await?;
+                    ensure_may_buy(txn, &user, &product).await?;
+
+                    let purchase = transaction::ActiveModel {
+                        kind: Set(Kind::Purchase),
+                        user: Set(Some(user.id)),
+                        product: Set(Some(product.id)),
+                        amount: Set(-product.price),
+                        event: Set(user.event),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+                    let data = LedgerEntry::from(purchase.clone());
+                    webhooks::emit(txn, webhook_event::Kind::Purchase, &data).await?;
+
+                    let balance = user.balance;
+                    let mut user = user.into_active_model();
+                    user.balance = Set(balance - product.price);
+                    let product = products::take_from_stock(txn, product, 1).await?;
+
+                    Ok((user.update(txn).await?, product, purchase))
+                })
+            })
+            .await?)
+    }
+
+    async fn sell(
+        &self,
+        product: i32,
+        count: i32,
+        age_verified: bool,
+    ) -> Result<(product::Model, Vec<transaction::Model>)> {
+        Ok(self
+            .db
+            .orm
+            .transaction::<_, _, AppError>(|txn| {
+                Box::pin(async move {
+                    let product = find_product(txn, product).await?;
+                    products::ensure_sellable(&product, count, age_verified)?;
+
+                    let mut purchases = Vec::new();
+                    for _ in 0..count {
+                        let purchase = transaction::ActiveModel {
+                            kind: Set(Kind::Purchase),
+                            product: Set(Some(product.id)),
+                            amount: Set(-product.price),
+                            ..Default::default()
+                        }
+                        .insert(txn)
+                        .await?;
+                        let data = LedgerEntry::from(purchase.clone());
+                        webhooks::emit(txn, webhook_event::Kind::Purchase, &data).await?;
+                        purchases.push(purchase);
+                    }
+
+                    let product = products::take_from_stock(txn, product, count).await?;
+                    Ok((product, purchases))
+                })
+            })
+            .await?)
+    }
+
+    async fn transfer(
+        &self,
+        sender: i32,
+        receiver: i32,
+        amount: i32,
+    ) -> Result<(user::Model, user::Model, Vec<transaction::Model>)> {
+        Ok(self
+            .db
+            .orm
+            .transaction::<_, _, AppError>(|txn| {
+                Box::pin(async move {
+                    let sender = find_user(txn, sender).await?;
+                    let receiver = find_user(txn, receiver).await?;
+                    ensure_bookable(&sender)?;
+                    ensure_bookable(&receiver)?;
+                    let (s_id, r_id) = (sender.id, receiver.id);
+                    let (s_balance, r_balance) = (sender.balance, receiver.balance);
+
+                    let mut sender = sender.into_active_model();
+                    sender.balance = Set(s_balance - amount);
+                    let sender = sender.update(txn).await?;
+                    let mut receiver = receiver.into_active_model();
+                    receiver.balance = Set(r_balance + amount);
+                    let receiver = receiver.update(txn).await?;
+
+                    let mut transfers = Vec::new();
+                    for (user, amount) in [(s_id, -amount), (r_id, amount)] {
+                        let transfer = transaction::ActiveModel {
+                            kind: Set(Kind::Transfer),
+                            user: Set(Some(user)),
+                            amount: Set(amount),
+                            ..Default::default()
+                        }
+                        .insert(txn)
+                        .await?;
+                        transfers.push(transfer);
+                    }
+
+                    let data = WebhookTransfer {
+                        sender: s_id,
+                        receiver: r_id,
+                        amount,
+                    };
+                    webhooks::emit(txn, webhook_event::Kind::Transfer, &data).await?;
+                    Ok((sender, receiver, transfers))
+                })
+            })
+            .await?)
+    }
+
+    async fn purchases(&self, user: i32, since: NaiveDateTime) -> Result<Vec<transaction::Model>> {
+        Ok(transaction::Entity::find()
+            .filter(transaction::Column::Kind.eq(Kind::Purchase))
+            .filter(transaction::Column::User.eq(user))
+            .filter(transaction::Column::CreatedAt.gte(since))
+            .all(&self.db.orm)
+            .await?)
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use axum::async_trait;
+use chrono::{NaiveDateTime, Utc};
+
+use super::{ProductRepository, TransactionRepository, UserRepository};
+use crate::{
+    entity::{
+        product,
+        transaction::{self, Kind},
+        user,
+    },
+    models::{ProductCreateRequest, ProductEditRequest, UserCreateRequest, UserEditRequest},
+    products::ensure_sellable,
+    user::ensure_bookable,
+    utils::{AppError, Result},
+};
+
+/// Repositories keeping everything in memory, meant for tests of handlers.
+/// Like the database, users and products referenced by the ledger can't be
+/// deleted. Webhooks are not sent. Temporary users of events can only be
+/// created in the database, so the checks for closed events are only covered
+/// by tests against it.
+#[derive(Debug, Default)]
+pub(crate) struct Memory {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: BTreeMap<i32, user::Model>,
+    products: BTreeMap<i32, product::Model>,
+    transactions: Vec<transaction::Model>,
+    last_id: i32,
+}
+
+impl State {
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn user(&mut self, id: i32) -> Result<&mut user::Model> {
+        self.users.get_mut(&id).ok_or(AppError::NotFount)
+    }
+
+    fn product(&mut self, id: i32) -> Result<&mut product::Model> {
+        self.products.get_mut(&id).ok_or(AppError::NotFount)
+    }
+
+    fn record(
+        &mut self,
+        kind: Kind,
+        user: Option<i32>,
+        product: Option<i32>,
+        amount: i32,
+    ) -> transaction::Model {
+        // purchases are attributed to the event of the user, like in the database
+        let event = match kind {
+            Kind::Purchase => user
+                .and_then(|id| self.users.get(&id))
+                .and_then(|u| u.event),
+            _ => None,
+        };
+        let transaction = transaction::Model {
+            id: self.next_id(),
+            kind,
+            user,
+            product,
+            amount,
+            created_at: Utc::now().naive_utc(),
+            reference: None,
+            event,
+            group_account: None,
+        };
+        self.transactions.push(transaction.clone());
+        transaction
+    }
+
+    /// removes sold items from the stock, if the stock of the product is tracked
+    fn take_from_stock(&mut self, id: i32, count: i32) -> Result<product::Model> {
+        let product = self.product(id)?;
+        if let Some(stock) = product.stock.as_mut() {
+            *stock -= count;
+            product.updated_at = Utc::now().naive_utc();
+        }
+        Ok(product.clone())
+    }
+}
+
+impl Memory {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // a panicking test must not break the others
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[async_trait]
+impl UserRepository for Memory {
+    async fn all(&self) -> Result<Vec<user::Model>> {
+        Ok(self.state().users.values().cloned().collect())
+    }
+
+    async fn find(&self, id: i32) -> Result<user::Model> {
+        Ok(self.state().user(id)?.clone())
+    }
+
+    async fn create(&self, user: UserCreateRequest) -> Result<user::Model> {
+        let mut state = self.state();
+        if state.users.values().any(|u| u.name == user.name) {
+            return Err(AppError::Conflict);
+        }
+
+        let now = Utc::now().naive_utc();
+        let user = user::Model {
+            id: state.next_id(),
+            name: user.name,
+            email: user.email,
+            created_at: now,
+            updated_at: now,
+            balance: user.balance.unwrap_or(0),
+            active: user.active.unwrap_or(true),
+            audit: user.audit.unwrap_or(false),
+            redirect: user.redirect.unwrap_or(true),
+            avatar: user.avatar,
+            age_verified: user.age_verified.unwrap_or(false),
+            event: None,
+            merged_into: None,
+            archived_at: None,
+            notifications: user.notifications.unwrap_or(true),
+        };
+        state.users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn edit(&self, id: i32, edit: UserEditRequest) -> Result<user::Model> {
+        let mut state = self.state();
+        if let Some(name) = &edit.name {
+            if state.users.values().any(|u| &u.name == name && u.id != id) {
+                return Err(AppError::Conflict);
+            }
+        }
+
+        let user = state.user(id)?;
+        user.name = edit.name.unwrap_or_else(|| user.name.clone());
+        user.email = edit.email.or_else(|| user.email.clone());
+        user.balance = edit.balance.unwrap_or(user.balance);
+        user.active = edit.active.unwrap_or(user.active);
+        user.audit = edit.audit.unwrap_or(user.audit);
+        user.redirect = edit.redirect.unwrap_or(user.redirect);
+        user.avatar = edit.avatar.or(user.avatar);
+        user.age_verified = edit.age_verified.unwrap_or(user.age_verified);
+        user.notifications = edit.notifications.unwrap_or(user.notifications);
+        user.updated_at = Utc::now().naive_utc();
+        Ok(user.clone())
+    }
+
+    async fn delete(&self, id: i32) -> Result<()> {
+        let mut state = self.state();
+        state.user(id)?;
+        if state.transactions.iter().any(|t| t.user == Some(id)) {
+            return Err(AppError::Referenced);
+        }
+        state.users.remove(&id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProductRepository for Memory {
+    async fn all(&self) -> Result<Vec<product::Model>> {
+        Ok(self.state().products.values().cloned().collect())
+    }
+
+    async fn find(&self, id: i32) -> Result<product::Model> {
+        Ok(self.state().product(id)?.clone())
+    }
+
+    async fn create(&self, product: ProductCreateRequest) -> Result<product::Model> {
+        let mut state = self.state();
+        if state.products.values().any(|p| p.name == product.name) {
+            return Err(AppError::Conflict);
+        }
+
+        let now = Utc::now().naive_utc();
+        let product = product::Model {
+            id: state.next_id(),
+            name: product.name,
+            caffeine: product.caffeine,
+            alcohol: product.alcohol,
+            energy: product.energy,
+            sugar: product.sugar,
+            volume: product.volume,
+            price: product.price.unwrap_or_default(),
+            created_at: now,
+            updated_at: now,
+            active: product.active.unwrap_or(true),
+            image: None,
+            age_restricted: product.age_restricted.unwrap_or(false),
+            stock: product.stock,
+        };
+        state.products.insert(product.id, product.clone());
+        Ok(product)
+    }
+
+    async fn edit(&self, id: i32, edit: ProductEditRequest) -> Result<product::Model> {
+        let mut state = self.state();
+        if let Some(name) = &edit.name {
+            if state
+                .products
+                .values()
+                .any(|p| &p.name == name && p.id != id)
+            {
+                return Err(AppError::Conflict);
+            }
+        }
+
+        let product = state.product(id)?;
+        product.name = edit.name.unwrap_or_else(|| product.name.clone());
+        product.caffeine = edit.caffeine.or(product.caffeine);
+        product.alcohol = edit.alcohol.or(product.alcohol);
+        product.energy = edit.energy.or(product.energy);
+        product.sugar = edit.sugar.or(product.sugar);
+        product.volume = edit.volume.or(product.volume);
+        product.price = edit.price.unwrap_or(product.price);
+        product.active = edit.active.unwrap_or(product.active);
+        product.image = edit.image.or(product.image);
+        product.age_restricted = edit.age_restricted.unwrap_or(product.age_restricted);
+        product.stock = edit.stock.or(product.stock);
+        product.updated_at = Utc::now().naive_utc();
+        Ok(product.clone())
+    }
+
+    async fn delete(&self, id: i32) -> Result<()> {
+        let mut state = self.state();
+        state.product(id)?;
+        if state.transactions.iter().any(|t| t.product == Some(id)) {
+            return Err(AppError::Referenced);
+        }
+        state.products.remove(&id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransactionRepository for Memory {
+    async fn book(
+        &self,
+        user: i32,
+        kind: Kind,
+        amount: i32,
+    ) -> Result<(user::Model, transaction::Model)> {
+        let mut state = self.state();
+        let user = state.user(user)?;
+        ensure_bookable(user)?;
+        user.balance += amount;
+        user.updated_at = Utc::now().naive_utc();
+        let user = user.clone();
+        let transaction = state.record(kind, Some(user.id), None, amount);
+        Ok((user, transaction))
+    }
+
+    async fn buy(
+        &self,
+        user: i32,
+        product: i32,
+    ) -> Result<(user::Model, product::Model, transaction::Model)> {
+        let mut state = self.state();
+        let product = state.product(product)?.clone();
+        let user = state.user(user)?;
+        ensure_bookable(user)?;
+        if product.age_restricted && !user.age_verified {
+            return Err(AppError::AgeRestricted);
+        }
+
+        user.balance -= product.price;
+        user.updated_at = Utc::now().naive_utc();
+        let user = user.clone();
+        let purchase = state.record(
+            Kind::Purchase,
+            Some(user.id),
+            Some(product.id),
+            -product.price,
+        );
+        let product = state.take_from_stock(product.id, 1)?;
+        Ok((user, product, purchase))
+    }
+
+    async fn sell(
+        &self,
+        product: i32,
+        count: i32,
+        age_verified: bool,
+    ) -> Result<(product::Model, Vec<transaction::Model>)> {
+        let mut state = self.state();
+        let product = state.product(product)?.clone();
+        ensure_sellable(&product, count, age_verified)?;
+        let purchases = (0..count)
+            .map(|_| state.record(Kind::Purchase, None, Some(product.id), -product.price))
+            .collect();
+        Ok((state.take_from_stock(product.id, count)?, purchases))
+    }
+
+    async fn transfer(
+        &self,
+        sender: i32,
+        receiver: i32,
+        amount: i32,
+    ) -> Result<(user::Model, user::Model, Vec<transaction::Model>)> {
+        let mut state = self.state();
+        // both have to exist before anything is changed
+        ensure_bookable(state.user(sender)?)?;
+        ensure_bookable(state.user(receiver)?)?;
+        let now = Utc::now().naive_utc();
+        let mut changed = Vec::new();
+        for (id, amount) in [(sender, -amount), (receiver, amount)] {
+            let user = state.user(id)?;
+            user.balance += amount;
+            user.updated_at = now;
+            changed.push(user.clone());
+        }
+
+        let transfers = vec![
+            state.record(Kind::Transfer, Some(sender), None, -amount),
+            state.record(Kind::Transfer, Some(receiver), None, amount),
+        ];
+        let receiver = changed.pop().expect("receiver was changed");
+        let sender = changed.pop().expect("sender was changed");
+        Ok((sender, receiver, transfers))
+    }
+
+    async fn purchases(&self, user: i32, since: NaiveDateTime) -> Result<Vec<transaction::Model>> {
+        Ok(self
+            .state()
+            .transactions
+            .iter()
+            .filter(|t| t.kind == Kind::Purchase && t.user == Some(user) && t.created_at >= since)
+            .cloned()
+            .collect())
+    }
+}
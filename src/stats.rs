@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Extension, Query},
+    routing, Json, Router,
+};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Weekday};
+use sea_orm::{entity::*, query::*, ConnectionTrait};
+
+use crate::{
+    entity::{
+        product,
+        transaction::{self, Kind},
+        user,
+    },
+    models::{
+        Bucket, HourStats, ProductStats, StatsQuery, TimeSeriesEntry, TopConsumer, WeekdayStats,
+    },
+    storage::Db,
+    utils::Result,
+};
+
+const DEFAULT_TOP_LIMIT: u64 = 10;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/products", routing::get(products))
+        .route("/hours", routing::get(hours))
+        .route("/weekdays", routing::get(weekdays))
+        .route("/revenue", routing::get(revenue))
+        .route("/deposits", routing::get(deposits))
+        .route("/top", routing::get(top))
+}
+
+/// converts a local date into the utc timestamp of its midnight
+pub(crate) fn local_midnight(date: NaiveDate) -> NaiveDateTime {
+    let midnight = date.and_hms(0, 0, 0);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|dt| dt.naive_utc())
+        .unwrap_or(midnight)
+}
+
+pub(crate) fn to_local(timestamp: NaiveDateTime) -> NaiveDateTime {
+    Local.from_utc_datetime(&timestamp).naive_local()
+}
+
+/// restricts a query to rows whose `column` lies within the given days
+pub(crate) fn in_range<E>(
+    mut select: Select<E>,
+    column: E::Column,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Select<E>
+where
+    E: EntityTrait,
+{
+    if let Some(from) = from {
+        select = select.filter(column.gte(local_midnight(from)));
+    }
+    if let Some(to) = to {
+        select = select.filter(column.lt(local_midnight(to.succ())));
+    }
+    select
+}
+
+/// all ledger entries of the given kind within the requested date range
+pub(crate) fn transactions(kind: Kind, query: &StatsQuery) -> Select<transaction::Entity> {
+    in_range(
+        transaction::Entity::find().filter(transaction::Column::Kind.eq(kind)),
+        transaction::Column::CreatedAt,
+        query.from,
+        query.to,
+    )
+}
+
+/// first day of the bucket a given day belongs to
+pub(crate) fn bucket_start(date: NaiveDate, bucket: Bucket) -> NaiveDate {
+    match bucket {
+        Bucket::Day => date,
+        Bucket::Week => date - Duration::days(date.weekday().num_days_from_monday().into()),
+        Bucket::Month => date.with_day(1).expect("every month has a first day"),
+    }
+}
+
+/// Sums the transactions per bucket. Purchases are booked negative, so they are
+/// negated to count as revenue, while corrections keep their sign and lower the
+/// sum of their bucket.
+fn time_series(
+    transactions: impl IntoIterator<Item = transaction::Model>,
+    bucket: Bucket,
+) -> Vec<TimeSeriesEntry> {
+    let mut series = BTreeMap::new();
+    for t in transactions {
+        let start = bucket_start(to_local(t.created_at).date(), bucket);
+        let entry = series.entry(start).or_insert(TimeSeriesEntry {
+            start,
+            count: 0,
+            amount: 0,
+        });
+        entry.count += 1;
+        entry.amount += match t.kind {
+            Kind::Purchase => -t.amount,
+            _ => t.amount,
+        };
+    }
+    series.into_values().collect()
+}
+
+/// number of purchases and revenue per product
+async fn products(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<ProductStats>>> {
+    let purchases = transactions(Kind::Purchase, &query).all(&db.orm).await?;
+    Ok(Json(product_stats(
+        &purchases,
+        &product_names(&db.orm).await?,
+    )))
+}
+
+pub(crate) async fn product_names(db: &impl ConnectionTrait) -> Result<HashMap<i32, String>> {
+    Ok(product::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p.name))
+        .collect())
+}
+
+/// number of sales and revenue per product, best selling first
+pub(crate) fn product_stats(
+    purchases: &[transaction::Model],
+    names: &HashMap<i32, String>,
+) -> Vec<ProductStats> {
+    let mut stats = HashMap::new();
+    for t in purchases {
+        let product = match t.product {
+            Some(product) => product,
+            None => continue,
+        };
+        let entry = stats.entry(product).or_insert_with(|| ProductStats {
+            product,
+            name: names.get(&product).cloned().unwrap_or_default(),
+            ..Default::default()
+        });
+        entry.count += 1;
+        entry.revenue -= t.amount;
+    }
+
+    let mut stats = stats.into_values().collect::<Vec<_>>();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.product.cmp(&b.product)));
+    stats
+}
+
+/// number of purchases per hour of the day
+async fn hours(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<HourStats>>> {
+    let mut stats = (0..24)
+        .map(|hour| HourStats { hour, count: 0 })
+        .collect::<Vec<_>>();
+    for t in transactions(Kind::Purchase, &query).all(&db.orm).await? {
+        stats[to_local(t.created_at).hour() as usize].count += 1;
+    }
+    Ok(Json(stats))
+}
+
+/// number of purchases per day of the week, starting with monday
+async fn weekdays(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<WeekdayStats>>> {
+    let mut stats = std::iter::successors(Some(Weekday::Mon), |d| Some(d.succ()))
+        .take(7)
+        .map(|weekday| WeekdayStats { weekday, count: 0 })
+        .collect::<Vec<_>>();
+    for t in transactions(Kind::Purchase, &query).all(&db.orm).await? {
+        let weekday = to_local(t.created_at).weekday();
+        stats[weekday.num_days_from_monday() as usize].count += 1;
+    }
+    Ok(Json(stats))
+}
+
+/// revenue of all purchases over time
+async fn revenue(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<TimeSeriesEntry>>> {
+    let transactions = transactions(Kind::Purchase, &query).all(&db.orm).await?;
+    Ok(Json(time_series(
+        transactions,
+        query.bucket.unwrap_or_default(),
+    )))
+}
+
+/// deposited money over time
+async fn deposits(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<TimeSeriesEntry>>> {
+    let transactions = transactions(Kind::Deposit, &query).all(&db.orm).await?;
+    Ok(Json(time_series(
+        transactions,
+        query.bucket.unwrap_or_default(),
+    )))
+}
+
+/// users with the most purchases
+async fn top(
+    Query(query): Query<StatsQuery>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<TopConsumer>>> {
+    let names = user::Entity::find()
+        .all(&db.orm)
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.name))
+        .collect::<HashMap<_, _>>();
+
+    let mut stats = HashMap::new();
+    for t in transactions(Kind::Purchase, &query).all(&db.orm).await? {
+        let user = match t.user {
+            Some(user) => user,
+            None => continue,
+        };
+        let entry = stats.entry(user).or_insert_with(|| TopConsumer {
+            user,
+            name: names.get(&user).cloned().unwrap_or_default(),
+            ..Default::default()
+        });
+        entry.count += 1;
+        entry.amount -= t.amount;
+    }
+
+    let mut stats = stats.into_values().collect::<Vec<_>>();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.user.cmp(&b.user)));
+    stats.truncate(query.limit.unwrap_or(DEFAULT_TOP_LIMIT) as usize);
+    Ok(Json(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn bucket_start_of_week_is_monday() {
+        let date = NaiveDate::from_ymd(2022, 3, 10);
+
+        assert_eq!(bucket_start(date, Bucket::Day), date);
+        assert_eq!(
+            bucket_start(date, Bucket::Week),
+            NaiveDate::from_ymd(2022, 3, 7)
+        );
+        assert_eq!(
+            bucket_start(date, Bucket::Month),
+            NaiveDate::from_ymd(2022, 3, 1)
+        );
+    }
+
+    #[test]
+    fn time_series_sums_per_bucket() {
+        let entry = |day, amount| transaction::Model {
+            id: 0,
+            kind: Kind::Purchase,
+            user: Some(1),
+            product: Some(1),
+            amount,
+            created_at: to_utc(NaiveDate::from_ymd(2022, 3, day).and_hms(12, 0, 0)),
+            reference: None,
+            event: None,
+            group_account: None,
+        };
+        let series = time_series(
+            vec![entry(1, -150), entry(2, -100), entry(14, -150)],
+            Bucket::Week,
+        );
+
+        assert_eq!(
+            series,
+            vec![
+                TimeSeriesEntry {
+                    start: NaiveDate::from_ymd(2022, 2, 28),
+                    count: 2,
+                    amount: 250,
+                },
+                TimeSeriesEntry {
+                    start: NaiveDate::from_ymd(2022, 3, 14),
+                    count: 1,
+                    amount: 150,
+                },
+            ]
+        );
+
+        // a deposit taken back again lowers the sum of deposits
+        let deposits = time_series(
+            vec![
+                transaction::Model {
+                    kind: Kind::Deposit,
+                    ..entry(1, 500)
+                },
+                transaction::Model {
+                    kind: Kind::Deposit,
+                    ..entry(2, -200)
+                },
+            ],
+            Bucket::Week,
+        );
+        assert_eq!(
+            deposits,
+            vec![TimeSeriesEntry {
+                start: NaiveDate::from_ymd(2022, 2, 28),
+                count: 2,
+                amount: 300,
+            }]
+        );
+    }
+
+    fn to_utc(local: NaiveDateTime) -> NaiveDateTime {
+        Local.from_local_datetime(&local).unwrap().naive_utc()
+    }
+}
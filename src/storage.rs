@@ -1,18 +1,293 @@
 use eyre::{eyre, Context, Result};
-use sea_orm::{DatabaseConnection, SqlxSqliteConnector};
-use sqlx::SqlitePool;
+use sea_orm::DatabaseConnection;
+use sqlx::migrate::Migrator;
+
+use crate::config::StorageConfig;
+
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("at least one storage backend has to be enabled, `sqlite` or `postgres`");
+
+/// migrations embedded at compile time, one set per sql dialect
+#[cfg(feature = "sqlite")]
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!();
+#[cfg(feature = "postgres")]
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// connection pool of the backend selected by the scheme of the database url
+#[derive(Debug, Clone)]
+pub enum Pool {
+    #[cfg(feature = "sqlite")]
+    Sqlite(sqlx::SqlitePool),
+    #[cfg(feature = "postgres")]
+    Postgres(sqlx::PgPool),
+}
+
+impl Pool {
+    /// the pool if the database is sqlite, some tools only work with it
+    pub fn sqlite(&self) -> Option<&sqlx::SqlitePool> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(pool) => Some(pool),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// waits until all connections are closed
+    pub async fn close(&self) {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(pool) => pool.close().await,
+            #[cfg(feature = "postgres")]
+            Pool::Postgres(pool) => pool.close().await,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Db {
     pub orm: DatabaseConnection,
-    pub pool: SqlitePool,
+    pub pool: Pool,
+}
+
+#[cfg(feature = "sqlite")]
+impl From<crate::config::JournalMode> for sqlx::sqlite::SqliteJournalMode {
+    fn from(mode: crate::config::JournalMode) -> Self {
+        use crate::config::JournalMode;
+
+        match mode {
+            JournalMode::Delete => Self::Delete,
+            JournalMode::Truncate => Self::Truncate,
+            JournalMode::Persist => Self::Persist,
+            JournalMode::Memory => Self::Memory,
+            JournalMode::Wal => Self::Wal,
+            JournalMode::Off => Self::Off,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<crate::config::Synchronous> for sqlx::sqlite::SqliteSynchronous {
+    fn from(synchronous: crate::config::Synchronous) -> Self {
+        use crate::config::Synchronous;
+
+        match synchronous {
+            Synchronous::Off => Self::Off,
+            Synchronous::Normal => Self::Normal,
+            Synchronous::Full => Self::Full,
+            Synchronous::Extra => Self::Extra,
+        }
+    }
 }
 
-pub async fn open_db(conn_str: impl AsRef<str>) -> Result<Db> {
-    let pool = SqlitePool::connect(conn_str.as_ref())
-        .await
-        .wrap_err_with(|| eyre!("unable to open database"))?;
-    let orm = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool.clone());
+/// Connects to `sqlite://...` or `postgres://...`, depending on the backends
+/// enabled at compile time.
+pub async fn open_db(config: &StorageConfig) -> Result<Db> {
+    let conn_str = config.database.as_str();
+    match conn_str.split(':').next().unwrap_or_default() {
+        #[cfg(feature = "sqlite")]
+        "sqlite" => {
+            let options = conn_str
+                .parse::<sqlx::sqlite::SqliteConnectOptions>()
+                .wrap_err_with(|| eyre!("invalid database {:?}", conn_str))?
+                .journal_mode(config.journal_mode.into())
+                .synchronous(config.synchronous.into())
+                .busy_timeout(std::time::Duration::from_millis(config.busy_timeout_ms))
+                .foreign_keys(config.foreign_keys);
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(config.max_connections)
+                .connect_with(options)
+                .await
+                .wrap_err_with(|| eyre!("unable to open database"))?;
+            let orm = sea_orm::SqlxSqliteConnector::from_sqlx_sqlite_pool(pool.clone());
+            Ok(Db {
+                orm,
+                pool: Pool::Sqlite(pool),
+            })
+        }
+        #[cfg(feature = "postgres")]
+        "postgres" | "postgresql" => {
+            let pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(config.max_connections)
+                .connect(conn_str)
+                .await
+                .wrap_err_with(|| eyre!("unable to open database"))?;
+            let orm = sea_orm::SqlxPostgresConnector::from_sqlx_postgres_pool(pool.clone());
+            Ok(Db {
+                orm,
+                pool: Pool::Postgres(pool),
+            })
+        }
+        scheme => Err(eyre!(
+            "unsupported database {:?}, enable its cargo feature",
+            scheme
+        )),
+    }
+}
+
+/// applies all pending migrations
+pub async fn migrate(db: &Db) -> Result<()> {
+    match &db.pool {
+        #[cfg(feature = "sqlite")]
+        Pool::Sqlite(pool) => SQLITE_MIGRATOR.run(pool).await,
+        #[cfg(feature = "postgres")]
+        Pool::Postgres(pool) => POSTGRES_MIGRATOR.run(pool).await,
+    }
+    .wrap_err_with(|| eyre!("unable to migrate database"))
+}
+
+/// versions of the embedded migrations that were not applied successfully
+pub async fn pending_migrations(db: &Db) -> Result<Vec<i64>> {
+    const APPLIED: &str = "SELECT version FROM _sqlx_migrations WHERE success = true";
+    let (migrator, applied): (_, Vec<i64>) = match &db.pool {
+        #[cfg(feature = "sqlite")]
+        Pool::Sqlite(pool) => (
+            &SQLITE_MIGRATOR,
+            sqlx::query_scalar(APPLIED).fetch_all(pool).await?,
+        ),
+        #[cfg(feature = "postgres")]
+        Pool::Postgres(pool) => (
+            &POSTGRES_MIGRATOR,
+            sqlx::query_scalar(APPLIED).fetch_all(pool).await?,
+        ),
+    };
+    Ok(migrator
+        .migrations
+        .iter()
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, response::IntoResponse};
+    use pretty_assertions::assert_eq;
+    use sea_orm::{entity::*, query::*, TransactionTrait};
+
+    use super::*;
+    use crate::{
+        entity::{
+            transaction::{self, Kind},
+            user,
+        },
+        utils::AppError,
+    };
+
+    async fn balance(db: &Db, id: i32) -> i32 {
+        user::Entity::find_by_id(id)
+            .one(&db.orm)
+            .await
+            .unwrap()
+            .unwrap()
+            .balance
+    }
+
+    #[tokio::test]
+    async fn backends_migrate_and_book_transactions() {
+        for db in crate::testing::backends("storage").await {
+            migrate(&db).await.unwrap();
+            assert_eq!(pending_migrations(&db).await.unwrap(), Vec::<i64>::new());
+
+            let alice = user::ActiveModel {
+                name: Set("alice".to_owned()),
+                balance: Set(0),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            assert!(alice.active && alice.notifications && alice.archived_at.is_none());
+
+            let duplicate = user::ActiveModel {
+                name: Set("alice".to_owned()),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap_err();
+            assert_eq!(
+                AppError::from(duplicate).into_response().status(),
+                StatusCode::CONFLICT
+            );
+
+            for fail in [false, true] {
+                let result = db
+                    .orm
+                    .transaction::<_, (), AppError>(|txn| {
+                        Box::pin(async move {
+                            transaction::ActiveModel {
+                                kind: Set(Kind::Purchase),
+                                user: Set(Some(alice.id)),
+                                amount: Set(-150),
+                                ..Default::default()
+                            }
+                            .insert(txn)
+                            .await?;
+                            user::ActiveModel {
+                                id: Unchanged(alice.id),
+                                balance: Set(-150),
+                                ..Default::default()
+                            }
+                            .update(txn)
+                            .await?;
+                            if fail {
+                                return Err(AppError::CapExceeded);
+                            }
+                            Ok(())
+                        })
+                    })
+                    .await;
+                assert_eq!(result.is_err(), fail);
+            }
+            assert_eq!(balance(&db, alice.id).await, -150);
+
+            let purchases = transaction::Entity::find()
+                .filter(transaction::Column::Kind.eq(Kind::Purchase))
+                .filter(transaction::Column::CreatedAt.lte(chrono::Utc::now().naive_utc()))
+                .all(&db.orm)
+                .await
+                .unwrap();
+            assert_eq!(purchases.len(), 1);
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_options_are_applied() {
+        let path =
+            std::env::temp_dir().join(format!("mateserver-{}-options.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = StorageConfig {
+            database: format!("sqlite://{}?mode=rwc", path.display()),
+            journal_mode: crate::config::JournalMode::Delete,
+            synchronous: crate::config::Synchronous::Normal,
+            ..Default::default()
+        };
+        let db = open_db(&config).await.unwrap();
+        migrate(&db).await.unwrap();
+        let pool = db.pool.sqlite().unwrap();
+
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let synchronous: i32 = sqlx::query_scalar("PRAGMA synchronous")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!((journal_mode.as_str(), synchronous), ("delete", 1));
 
-    Ok(Db { pool, orm })
+        // there is no user 42
+        let orphan = transaction::ActiveModel {
+            kind: Set(Kind::Deposit),
+            user: Set(Some(42)),
+            amount: Set(100),
+            ..Default::default()
+        }
+        .insert(&db.orm)
+        .await;
+        assert!(orphan.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,143 @@
+//! helpers shared by the tests of the handlers
+
+use axum::{
+    body::Body,
+    extract::Extension,
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use crate::{
+    config::{AdminConfig, Config, StorageConfig},
+    live::Live,
+    repository::Repositories,
+    storage::{self, Db},
+};
+
+/// admin token of [`config`]
+pub(crate) const ADMIN_TOKEN: &str = "secret";
+
+/// default config with [`ADMIN_TOKEN`] as admin token
+pub(crate) fn config() -> Config {
+    Config {
+        admin: AdminConfig {
+            token: Some(ADMIN_TOKEN.to_owned()),
+        },
+        ..Default::default()
+    }
+}
+
+/// Empty, migrated databases of every enabled backend for tests. SQLite is
+/// kept in memory, PostgreSQL is only used if a database is given in
+/// `MATEKASSE_TEST_POSTGRES`, e.g. `postgres://postgres@localhost/matekasse_test`.
+/// Every test gets a fresh database of its own there, suffixed with `name`.
+#[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+pub(crate) async fn backends(name: &str) -> Vec<Db> {
+    let mut backends = Vec::new();
+    #[cfg(feature = "sqlite")]
+    {
+        let config = StorageConfig {
+            database: "sqlite::memory:".to_owned(),
+            ..Default::default()
+        };
+        backends.push(storage::open_db(&config).await.unwrap());
+    }
+    #[cfg(feature = "postgres")]
+    if let Ok(url) = std::env::var("MATEKASSE_TEST_POSTGRES") {
+        use sea_orm::{ConnectionTrait, Statement};
+
+        let (server, database) = url.rsplit_once('/').expect("database url without database");
+        let (database, query) = database.split_at(database.find('?').unwrap_or(database.len()));
+        let database = format!("{}_{}", database, name);
+        let admin = storage::open_db(&StorageConfig {
+            database: url.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        for sql in [
+            format!("DROP DATABASE IF EXISTS \"{}\" WITH (FORCE)", database),
+            format!("CREATE DATABASE \"{}\"", database),
+        ] {
+            let statement = Statement::from_string(admin.orm.get_database_backend(), sql);
+            admin.orm.execute(statement).await.unwrap();
+        }
+        admin.pool.close().await;
+
+        let config = StorageConfig {
+            database: format!("{}/{}{}", server, database, query),
+            ..Default::default()
+        };
+        backends.push(storage::open_db(&config).await.unwrap());
+    }
+    for db in &backends {
+        storage::migrate(db).await.unwrap();
+    }
+    backends
+}
+
+/// In-memory repositories and the database backed ones of every backend,
+/// together with the database, if there is one.
+pub(crate) async fn repositories(name: &str) -> Vec<(Repositories, Option<Db>)> {
+    let mut repositories = vec![(Repositories::memory(), None)];
+    for db in backends(name).await {
+        repositories.push((Repositories::database(db.clone()), Some(db)));
+    }
+    repositories
+}
+
+/// the router with the repositories, the [`config`] and a live channel
+pub(crate) fn app(router: Router, repositories: Repositories, db: Option<Db>) -> Router {
+    let mut app = repositories
+        .layer(router)
+        .layer(Extension(config()))
+        .layer(Extension(Live::default()));
+    if let Some(db) = db {
+        app = app.layer(Extension(db));
+    }
+    app
+}
+
+/// Sends a request to the router and returns the status and the json body,
+/// plain text bodies are returned as json string.
+pub(crate) async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: impl Into<Body>,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(body.into())
+        .unwrap();
+    call(app, request).await
+}
+
+/// like [`send`], authenticated with the [`ADMIN_TOKEN`]
+pub(crate) async fn send_admin(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: impl Into<Body>,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .header(AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(body.into())
+        .unwrap();
+    call(app, request).await
+}
+
+async fn call(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into()));
+    (status, body)
+}
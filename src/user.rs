@@ -4,35 +4,52 @@ use axum::{
     routing, Json, Router,
 };
 use serde::Deserialize;
+use tracing::warn;
 
-use std::convert::TryInto;
-
-use sea_orm::{entity::*, TransactionTrait};
+use sea_orm::{entity::*, query::*, sea_query::Expr, ConnectionTrait, TransactionTrait};
 
 use crate::{
+    auth::Admin,
+    config::Config,
     entity::{
-        product,
+        bank_transaction, barcode, group_member, mete_import, product, recurring_charge_user,
+        transaction::{self, Kind},
         user::{self, Entity as UserModel},
+        webhook_event,
+    },
+    events,
+    journal::amount,
+    live::Live,
+    models::{
+        BuyResponse, FundsTransferRequest, LiveEvent, User, UserCreateRequest, UserEditRequest,
+        UserImportResponse, UserMergeRequest, UsersStatsResponce,
     },
-    models::{FundsTransferRequest, User, UserCreateRequest, UserEditRequest, UsersStatsResponce},
+    nutrition,
+    repository::{Products, Transactions, Users},
     storage::Db,
     utils::{AppError, Result},
+    webhooks,
 };
 
+mod import;
+
 pub fn router() -> Router {
     Router::new()
         .route("/", routing::get(get_all).post(create))
         .route("/stats", routing::get(stats))
+        .route("/import", routing::post(import))
         .route("/:id/:operation", routing::post(modify_balance))
         .route("/:id/buy", routing::post(buy))
         .route("/:id/transfer", routing::post(transfer))
+        .route("/:id/merge", routing::post(merge))
+        .route("/:id/nutrition", routing::get(nutrition::get))
         .route("/:id", routing::get(get).patch(edit).delete(delete))
 }
 
 /// returns all products
-async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<User>>> {
-    let users = UserModel::find()
-        .all(&db.orm)
+async fn get_all(Extension(users): Extension<Users>) -> Result<Json<Vec<User>>> {
+    let users = users
+        .all()
         .await?
         .into_iter()
         .map(Into::into)
@@ -43,8 +60,27 @@ async fn get_all(Extension(db): Extension<Db>) -> Result<Json<Vec<User>>> {
 
 async fn create(
     Json(user): Json<UserCreateRequest>,
-    Extension(db): Extension<Db>,
+    Extension(users): Extension<Users>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
 ) -> Result<(StatusCode, Json<User>)> {
+    if user.age_verified.is_some() && admin.is_none() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let user: User = users.create(user).await?.into();
+    live.publish([LiveEvent::User(user.clone())]);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// creates a user, temporary users belong to an event. The barcode is stored
+/// before the webhook is emitted, so it is part of the payload.
+pub(crate) async fn insert(
+    db: &impl ConnectionTrait,
+    user: UserCreateRequest,
+    event: Option<i32>,
+    barcode: Option<String>,
+) -> Result<user::Model> {
     let user = user::ActiveModel {
         name: Set(user.name),
         email: Set(user.email),
@@ -57,62 +93,87 @@ async fn create(
             .map(Option::Some)
             .map(ActiveValue::set)
             .unwrap_or_else(ActiveValue::not_set),
+        age_verified: Set(user.age_verified.unwrap_or(false)),
+        notifications: Set(user.notifications.unwrap_or(true)),
+        event: Set(event),
         ..Default::default()
     };
 
-    let user = user.save(&db.orm).await?.try_into()?;
-    Ok((StatusCode::CREATED, Json(user)))
+    let user = user.insert(db).await?;
+    if let Some(code) = &barcode {
+        barcode::ActiveModel {
+            code: Set(code.clone()),
+            user: Set(Some(user.id)),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+    }
+
+    let mut payload = User::from(user.clone());
+    payload.barcode = barcode;
+    webhooks::emit(db, webhook_event::Kind::UserCreated, &payload).await?;
+    Ok(user)
 }
 
-async fn delete(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<&'static str> {
-    UserModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?
-        .into_active_model()
-        .delete(&db.orm)
-        .await?;
+/// creates all users of a csv file at once, see [`import::import`]
+async fn import(
+    body: String,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    _: Admin,
+) -> Result<(StatusCode, Json<UserImportResponse>)> {
+    let (status, response) = import::import(&db, &body).await?;
+    live.publish(response.imported.iter().cloned().map(LiveEvent::User));
+    Ok((status, Json(response)))
+}
+
+/// entry point of the `import-users` command
+pub async fn run_import(db: &Db, path: std::path::PathBuf) -> eyre::Result<()> {
+    let data = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| eyre::eyre!("unable to read {}: {}", path.display(), e))?;
+    let (_, response) = import::import(db, &data)
+        .await
+        .map_err(|err| eyre::eyre!("{}", err))?;
+
+    for error in &response.errors {
+        println!("line {}: {}", error.line, error.message);
+    }
+    if !response.errors.is_empty() {
+        eyre::bail!("no users imported, {} errors", response.errors.len());
+    }
+    println!("imported {} users", response.imported.len());
+    Ok(())
+}
+
+async fn delete(
+    Path(id): Path<i32>,
+    Extension(users): Extension<Users>,
+    Extension(live): Extension<Live>,
+) -> Result<&'static str> {
+    users.delete(id).await?;
+    live.publish([LiveEvent::UserDeleted(id)]);
     Ok("user deleted")
 }
 
-async fn get(Path(id): Path<i32>, Extension(db): Extension<Db>) -> Result<Json<User>> {
-    let user = UserModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?
-        .into();
-    Ok(Json(user))
+async fn get(Path(id): Path<i32>, Extension(users): Extension<Users>) -> Result<Json<User>> {
+    Ok(Json(users.find(id).await?.into()))
 }
 
 async fn edit(
     Path(id): Path<i32>,
     Json(body): Json<UserEditRequest>,
-    Extension(db): Extension<Db>,
+    Extension(users): Extension<Users>,
+    Extension(live): Extension<Live>,
+    admin: Option<Admin>,
 ) -> Result<Json<User>> {
-    let user = UserModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?;
-
-    let mut user = user.into_active_model();
-
-    user.name = body.name.map(ActiveValue::set).unwrap_or(user.name);
-    user.email = body
-        .email
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(user.email);
-    user.balance = body.balance.map(ActiveValue::set).unwrap_or(user.balance);
-    user.active = body.active.map(ActiveValue::set).unwrap_or(user.active);
-    user.audit = body.audit.map(ActiveValue::set).unwrap_or(user.audit);
-    user.redirect = body.redirect.map(ActiveValue::set).unwrap_or(user.redirect);
-    user.avatar = body
-        .avatar
-        .map(Option::Some)
-        .map(ActiveValue::set)
-        .unwrap_or(user.avatar);
-
-    let user = user.save(&db.orm).await?.try_into()?;
+    if body.age_verified.is_some() && admin.is_none() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let user: User = users.edit(id, body).await?.into();
+    live.publish([LiveEvent::User(user.clone())]);
     Ok(Json(user))
 }
 
@@ -126,84 +187,243 @@ enum Operation {
 async fn modify_balance(
     Path((id, operation)): Path<(i32, Operation)>,
     body: String,
-    Extension(db): Extension<Db>,
+    Extension(transactions): Extension<Transactions>,
+    Extension(live): Extension<Live>,
 ) -> Result<Json<User>> {
     let amount = body.parse::<i32>()?;
-    let user = UserModel::find_by_id(id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?;
-    let balance = user.balance;
-    let mut user = user.into_active_model();
-    user.balance = Set(match operation {
-        Operation::Deposit => balance + amount,
-        Operation::Spend => balance - amount,
-    });
-
-    let user: User = user.save(&db.orm).await?.try_into()?;
+    let (kind, amount) = match operation {
+        Operation::Deposit => (Kind::Deposit, amount),
+        Operation::Spend => (Kind::Spend, -amount),
+    };
+    let (user, transaction) = transactions.book(id, kind, amount).await?;
+
+    let user = User::from(user);
+    live.publish([
+        LiveEvent::User(user.clone()),
+        LiveEvent::Transaction(transaction.into()),
+    ]);
     Ok(Json(user))
 }
 
+/// merged and archived users are kept for their history only, nothing can be
+/// booked for them anymore
+pub(crate) fn ensure_bookable(user: &user::Model) -> Result<()> {
+    if user.merged_into.is_some() || user.archived_at.is_some() {
+        return Err(AppError::Conflict);
+    }
+    Ok(())
+}
+
+/// checks whether the user is allowed to buy the product at all, regardless of
+/// who pays for it
+pub(crate) async fn ensure_may_buy(
+    db: &impl ConnectionTrait,
+    user: &user::Model,
+    product: &product::Model,
+) -> Result<()> {
+    ensure_bookable(user)?;
+    if product.age_restricted && !user.age_verified {
+        return Err(AppError::AgeRestricted);
+    }
+    if let Some(event) = user.event {
+        events::ensure_open(db, event).await?;
+    }
+    Ok(())
+}
+
 async fn buy(
     Path(user_id): Path<i32>,
     body: String,
-    Extension(db): Extension<Db>,
-) -> Result<Json<User>> {
+    Extension(config): Extension<Config>,
+    Extension(products): Extension<Products>,
+    Extension(transactions): Extension<Transactions>,
+    Extension(live): Extension<Live>,
+) -> Result<Json<BuyResponse>> {
     let product_id = body.parse::<i32>()?;
-    let product = product::Entity::find_by_id(product_id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?;
+    let (user, product, purchase) = transactions.buy(user_id, product_id).await?;
 
-    let user = UserModel::find_by_id(user_id)
-        .one(&db.orm)
-        .await?
-        .ok_or(AppError::NotFount)?;
-    let balance = user.balance;
-    let mut user = user.into_active_model();
-    user.balance = Set(balance - product.price);
-
-    let user: User = user.save(&db.orm).await?.try_into()?;
-    Ok(Json(user))
+    let user = User::from(user);
+    live.publish([
+        LiveEvent::User(user.clone()),
+        LiveEvent::Product(product.into()),
+        LiveEvent::Transaction(purchase.clone().into()),
+    ]);
+    // the purchase is booked already, failing now would only make clients retry it
+    let warnings = nutrition::warnings(&config.nutrition, &products, &transactions, &purchase)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("unable to check the intake of user {}: {:?}", user_id, err);
+            Vec::new()
+        });
+    Ok(Json(BuyResponse { user, warnings }))
 }
 
 async fn transfer(
     Path(sender): Path<i32>,
     Json(request): Json<FundsTransferRequest>,
-    Extension(db): Extension<Db>,
+    Extension(transactions): Extension<Transactions>,
+    Extension(live): Extension<Live>,
 ) -> Result<()> {
-    Ok(db
+    let (sender, receiver, transfers) = transactions
+        .transfer(sender, request.receiver, request.amount)
+        .await?;
+
+    live.publish(
+        [
+            LiveEvent::User(sender.into()),
+            LiveEvent::User(receiver.into()),
+        ]
+        .into_iter()
+        .chain(
+            transfers
+                .into_iter()
+                .map(|t| LiveEvent::Transaction(t.into())),
+        ),
+    );
+    Ok(())
+}
+
+/// Moves balance, barcodes, group memberships, recurring charges, history,
+/// bank transactions, the mete import and avatar of the source user to the
+/// user given in the path. The source user is
+/// archived afterwards.
+async fn merge(
+    Path(target_id): Path<i32>,
+    Json(request): Json<UserMergeRequest>,
+    Extension(db): Extension<Db>,
+    Extension(live): Extension<Live>,
+    _: Admin,
+) -> Result<Json<User>> {
+    if request.source == target_id {
+        return Err(AppError::InvalidInput(
+            "a user can not be merged into itself".to_string(),
+        ));
+    }
+
+    let (user, changes) = db
         .orm
-        .transaction::<_, (), AppError>(|txn| {
+        .transaction::<_, (User, Vec<LiveEvent>), AppError>(|txn| {
             Box::pin(async move {
-                let sender = UserModel::find_by_id(sender)
+                let source = UserModel::find_by_id(request.source)
                     .one(txn)
                     .await?
                     .ok_or(AppError::NotFount)?;
-                let s_balance = sender.balance;
-                let mut sender = sender.into_active_model();
-
-                let receiver = UserModel::find_by_id(request.receiver)
+                let target = UserModel::find_by_id(target_id)
                     .one(txn)
                     .await?
                     .ok_or(AppError::NotFount)?;
-                let r_balance = receiver.balance;
-                let mut receiver = receiver.into_active_model();
+                if source.merged_into.is_some() || target.merged_into.is_some() {
+                    return Err(AppError::Conflict);
+                }
+
+                barcode::Entity::update_many()
+                    .col_expr(barcode::Column::User, Expr::value(target.id))
+                    .filter(barcode::Column::User.eq(source.id))
+                    .exec(txn)
+                    .await?;
+                transaction::Entity::update_many()
+                    .col_expr(transaction::Column::User, Expr::value(target.id))
+                    .filter(transaction::Column::User.eq(source.id))
+                    .exec(txn)
+                    .await?;
+                bank_transaction::Entity::update_many()
+                    .col_expr(bank_transaction::Column::User, Expr::value(target.id))
+                    .filter(bank_transaction::Column::User.eq(source.id))
+                    .exec(txn)
+                    .await?;
+                // a new import of the mete user updates the target
+                mete_import::Entity::update_many()
+                    .col_expr(mete_import::Column::LocalId, Expr::value(target.id))
+                    .filter(mete_import::Column::Kind.eq(mete_import::Kind::User))
+                    .filter(mete_import::Column::LocalId.eq(source.id))
+                    .exec(txn)
+                    .await?;
+
+                // memberships in groups the target is already part of are dropped
+                let groups = group_member::Entity::find()
+                    .filter(group_member::Column::User.eq(target.id))
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|m| m.group_account)
+                    .collect::<Vec<_>>();
+                group_member::Entity::delete_many()
+                    .filter(group_member::Column::User.eq(source.id))
+                    .filter(group_member::Column::GroupAccount.is_in(groups))
+                    .exec(txn)
+                    .await?;
+                group_member::Entity::update_many()
+                    .col_expr(group_member::Column::User, Expr::value(target.id))
+                    .filter(group_member::Column::User.eq(source.id))
+                    .exec(txn)
+                    .await?;
+
+                // same for recurring charges, so nothing is charged twice
+                let charges = recurring_charge_user::Entity::find()
+                    .filter(recurring_charge_user::Column::User.eq(target.id))
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|c| c.charge)
+                    .collect::<Vec<_>>();
+                recurring_charge_user::Entity::delete_many()
+                    .filter(recurring_charge_user::Column::User.eq(source.id))
+                    .filter(recurring_charge_user::Column::Charge.is_in(charges))
+                    .exec(txn)
+                    .await?;
+                recurring_charge_user::Entity::update_many()
+                    .col_expr(recurring_charge_user::Column::User, Expr::value(target.id))
+                    .filter(recurring_charge_user::Column::User.eq(source.id))
+                    .exec(txn)
+                    .await?;
+
+                let merge = transaction::ActiveModel {
+                    kind: Set(Kind::Merge),
+                    user: Set(Some(target.id)),
+                    amount: Set(0),
+                    reference: Set(Some(format!(
+                        "merged {} ({}) with balance {}",
+                        source.name,
+                        source.id,
+                        amount(source.balance)
+                    ))),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
 
-                sender.balance = Set(s_balance - request.amount);
-                receiver.balance = Set(r_balance + request.amount);
+                let (balance, avatar) = (source.balance, source.avatar);
+                let mut archived = source.into_active_model();
+                archived.balance = Set(0);
+                archived.active = Set(false);
+                archived.avatar = Set(None);
+                archived.merged_into = Set(Some(target.id));
+                archived.archived_at = Set(Some(chrono::Utc::now().naive_utc()));
+                let archived = archived.update(txn).await?;
 
-                sender.save(txn).await?;
-                receiver.save(txn).await?;
+                let target_balance = target.balance;
+                let target_avatar = target.avatar;
+                let mut target = target.into_active_model();
+                target.balance = Set(target_balance + balance);
+                target.avatar = Set(target_avatar.or(avatar));
+                let target: User = target.update(txn).await?.into();
 
-                Ok(())
+                let changes = vec![
+                    LiveEvent::User(archived.into()),
+                    LiveEvent::User(target.clone()),
+                    LiveEvent::Transaction(merge.into()),
+                ];
+                Ok((target, changes))
             })
         })
-        .await?)
+        .await?;
+
+    live.publish(changes);
+    Ok(Json(user))
 }
 
-async fn stats(Extension(db): Extension<Db>) -> Result<Json<UsersStatsResponce>> {
-    let users = UserModel::find().all(&db.orm).await?;
+async fn stats(Extension(users): Extension<Users>) -> Result<Json<UsersStatsResponce>> {
+    let users = users.all().await?;
 
     let stats = users.iter().fold(
         UsersStatsResponce {
@@ -223,3 +443,188 @@ async fn stats(Extension(db): Extension<Db>) -> Result<Json<UsersStatsResponce>>
 
     Ok(Json(stats))
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        models::ProductCreateRequest,
+        testing::{self, send},
+    };
+
+    fn product(name: &str, price: i32, age_restricted: bool) -> ProductCreateRequest {
+        ProductCreateRequest {
+            name: name.to_string(),
+            caffeine: None,
+            alcohol: None,
+            energy: None,
+            sugar: None,
+            volume: None,
+            price: Some(price),
+            active: None,
+            image: None,
+            age_restricted: Some(age_restricted),
+            stock: Some(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn users_book_buy_and_transfer() {
+        for (repositories, db) in testing::repositories("users").await {
+            let mate = repositories
+                .products
+                .create(product("Club-Mate", 150, false))
+                .await
+                .unwrap();
+            let beer = repositories
+                .products
+                .create(product("Beer", 200, true))
+                .await
+                .unwrap();
+            let app = testing::app(router(), repositories, db);
+
+            let (status, alice) = send(&app, "POST", "/", r#"{"name": "alice"}"#).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let alice = alice["id"].as_i64().unwrap();
+            let (status, _) = send(&app, "POST", "/", r#"{"name": "alice"}"#).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let body = r#"{"name": "eve", "age_verified": true}"#;
+            let (status, _) = send(&app, "POST", "/", body).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            let (_, bob) = send(&app, "POST", "/", r#"{"name": "bob"}"#).await;
+            let bob = bob["id"].as_i64().unwrap();
+
+            let (status, user) = send(&app, "POST", &format!("/{}/deposit", alice), "500").await;
+            assert_eq!((status, &user["balance"]), (StatusCode::OK, &json!(500)));
+            let buy = format!("/{}/buy", alice);
+            let (_, user) = send(&app, "POST", &buy, mate.id.to_string()).await;
+            assert_eq!(user["balance"], json!(350));
+            let (status, _) = send(&app, "POST", &buy, beer.id.to_string()).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            let (status, _) = send(&app, "POST", "/999/buy", mate.id.to_string()).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+
+            let transfer = json!({"amount": 100, "receiver": bob}).to_string();
+            let (status, _) = send(&app, "POST", &format!("/{}/transfer", alice), transfer).await;
+            assert_eq!(status, StatusCode::OK);
+            let (_, user) = send(&app, "GET", &format!("/{}", bob), "").await;
+            assert_eq!(user["balance"], json!(100));
+            let (_, stats) = send(&app, "GET", "/stats", "").await;
+            assert_eq!(
+                stats,
+                json!({"user_count": 2, "active_count": 2, "balance_sum": 350})
+            );
+
+            let (status, _) = send(&app, "DELETE", &format!("/{}", bob), "").await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let (_, carol) = send(&app, "POST", "/", r#"{"name": "carol"}"#).await;
+            let carol = format!("/{}", carol["id"]);
+            let (status, _) = send(&app, "DELETE", &carol, "").await;
+            assert_eq!(status, StatusCode::OK);
+            let (status, _) = send(&app, "GET", &carol, "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn users_and_products_with_history_are_not_deleted() {
+        for (repositories, db) in testing::repositories("history").await {
+            let mate = repositories
+                .products
+                .create(product("Club-Mate", 150, false))
+                .await
+                .unwrap();
+            let app = testing::app(router(), repositories.clone(), db.clone());
+            let products = testing::app(crate::products::router(), repositories, db);
+
+            let (_, alice) = send(&app, "POST", "/", r#"{"name": "alice"}"#).await;
+            let alice = format!("/{}", alice["id"]);
+            send(&app, "POST", &format!("{}/buy", alice), mate.id.to_string()).await;
+
+            let (status, _) = send(&app, "DELETE", &alice, "").await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let (status, _) = send(&products, "DELETE", &format!("/{}", mate.id), "").await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let (status, _) = send(&app, "GET", &alice, "").await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_move_bank_transactions_and_mete_imports() {
+        for db in testing::backends("merge").await {
+            let repositories = crate::repository::Repositories::database(db.clone());
+            let app = testing::app(router(), repositories, Some(db.clone()));
+            let mut users = Vec::new();
+            for name in ["alice", "alice2"] {
+                let request = UserCreateRequest {
+                    name: name.to_owned(),
+                    ..Default::default()
+                };
+                users.push(insert(&db.orm, request, None, None).await.unwrap().id);
+            }
+            let (target, source) = (users[0], users[1]);
+            bank_transaction::ActiveModel {
+                bank_id: Set("1".to_owned()),
+                booked_at: Set(chrono::Utc::now().naive_utc().date()),
+                amount: Set(500),
+                reference: Set("alice2".to_owned()),
+                status: Set(bank_transaction::Status::Pending),
+                user: Set(Some(source)),
+                ..Default::default()
+            }
+            .insert(&db.orm)
+            .await
+            .unwrap();
+            for (kind, mete_id) in [
+                (mete_import::Kind::User, "7"),
+                (mete_import::Kind::Drink, "3"),
+            ] {
+                mete_import::ActiveModel {
+                    kind: Set(kind),
+                    mete_id: Set(mete_id.to_owned()),
+                    local_id: Set(source),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            send(&app, "POST", &format!("/{}/deposit", source), "300").await;
+
+            let body = json!({ "source": source }).to_string();
+            let (status, merged) =
+                testing::send_admin(&app, "POST", &format!("/{}/merge", target), body).await;
+            assert_eq!((status, &merged["balance"]), (StatusCode::OK, &json!(300)));
+            // the moved history already adds up to the balance of the target
+            let ledger = transaction::Entity::find()
+                .filter(transaction::Column::User.eq(target))
+                .all(&db.orm)
+                .await
+                .unwrap();
+            assert_eq!(ledger.iter().map(|t| t.amount).sum::<i32>(), 300);
+            let (status, _) = send(&app, "POST", &format!("/{}/deposit", source), "100").await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let transfer = json!({"amount": 100, "receiver": source}).to_string();
+            let (status, _) = send(&app, "POST", &format!("/{}/transfer", target), transfer).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+
+            let bank = bank_transaction::Entity::find().one(&db.orm).await.unwrap();
+            assert_eq!(bank.unwrap().user, Some(target));
+            let imports = mete_import::Entity::find()
+                .order_by_asc(mete_import::Column::Id)
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|i| i.local_id)
+                .collect::<Vec<_>>();
+            // only users are repointed, other ids are not user ids
+            assert_eq!(imports, vec![target, source]);
+        }
+    }
+}
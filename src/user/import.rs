@@ -0,0 +1,373 @@
+use std::collections::HashSet;
+
+use axum::http::StatusCode;
+use sea_orm::{entity::*, DatabaseTransaction, TransactionTrait};
+
+use crate::{
+    entity::{
+        barcode,
+        transaction::{self, Kind},
+        user,
+    },
+    models::{User, UserCreateRequest, UserImportError, UserImportResponse},
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// a user to be created by a bulk import
+#[derive(Debug, Clone, PartialEq)]
+struct ImportRow {
+    line: usize,
+    name: String,
+    email: Option<String>,
+    /// initial balance in cent
+    balance: i32,
+    barcode: Option<String>,
+    audit: bool,
+}
+
+#[derive(Debug)]
+struct RowError {
+    line: usize,
+    field: &'static str,
+    error: AppError,
+}
+
+impl RowError {
+    fn invalid(line: usize, field: &'static str, message: String) -> Self {
+        RowError {
+            line,
+            field,
+            error: AppError::InvalidInput(message),
+        }
+    }
+
+    fn conflict(line: usize, field: &'static str) -> Self {
+        RowError {
+            line,
+            field,
+            error: AppError::Conflict,
+        }
+    }
+}
+
+impl From<&RowError> for UserImportError {
+    fn from(e: &RowError) -> Self {
+        UserImportError {
+            line: e.line,
+            message: format!("{}: {}", e.field, e.error),
+        }
+    }
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "" | "0" | "false" | "no" => Some(false),
+        "1" | "true" | "yes" | "x" => Some(true),
+        _ => None,
+    }
+}
+
+/// Parses a csv file with the columns `name`, `email`, `balance` (in cent),
+/// `barcode` and `audit`, only `name` is required. A missing header fails the
+/// whole file, invalid fields are reported per line.
+fn parse_csv(data: &str) -> std::result::Result<(Vec<ImportRow>, Vec<RowError>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data.as_bytes());
+
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let name_column = column("name").ok_or("missing column: name")?;
+    let columns = [
+        column("email"),
+        column("balance"),
+        column("barcode"),
+        column("audit"),
+    ];
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let line = row + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(RowError::invalid(line, "line", e.to_string()));
+                continue;
+            }
+        };
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .unwrap_or_default()
+                .trim()
+                .to_owned()
+        };
+        let [email, balance, barcode, audit] = columns.map(field);
+
+        let name = field(Some(name_column));
+        if name.is_empty() {
+            errors.push(RowError::invalid(
+                line,
+                "name",
+                "must not be empty".to_owned(),
+            ));
+        }
+        if !email.is_empty() && !email.contains('@') {
+            errors.push(RowError::invalid(
+                line,
+                "email",
+                format!("{:?} is no email address", email),
+            ));
+        }
+        let balance = match balance.as_str() {
+            "" => 0,
+            value => value.parse().unwrap_or_else(|e| {
+                errors.push(RowError::invalid(
+                    line,
+                    "balance",
+                    format!("{:?}: {}", value, e),
+                ));
+                0
+            }),
+        };
+        let audit = parse_flag(&audit).unwrap_or_else(|| {
+            errors.push(RowError::invalid(
+                line,
+                "audit",
+                format!("{:?} is neither true nor false", audit),
+            ));
+            false
+        });
+
+        rows.push(ImportRow {
+            line,
+            name,
+            email: Some(email).filter(|e| !e.is_empty()),
+            balance,
+            barcode: Some(barcode).filter(|b| !b.is_empty()),
+            audit,
+        });
+    }
+
+    Ok((rows, errors))
+}
+
+/// names and barcodes have to be unique within the file and the database
+fn check_conflicts(
+    rows: &[ImportRow],
+    mut names: HashSet<String>,
+    mut barcodes: HashSet<String>,
+) -> Vec<RowError> {
+    let mut errors = Vec::new();
+    for row in rows {
+        if !row.name.is_empty() && !names.insert(row.name.clone()) {
+            errors.push(RowError::conflict(row.line, "name"));
+        }
+        if let Some(barcode) = &row.barcode {
+            if !barcodes.insert(barcode.clone()) {
+                errors.push(RowError::conflict(row.line, "barcode"));
+            }
+        }
+    }
+    errors
+}
+
+async fn insert(txn: &DatabaseTransaction, rows: Vec<ImportRow>) -> Result<UserImportResponse> {
+    let mut response = UserImportResponse::default();
+    for row in rows {
+        let request = UserCreateRequest {
+            name: row.name,
+            email: row.email,
+            balance: Some(row.balance),
+            audit: Some(row.audit),
+            ..Default::default()
+        };
+        let user = crate::user::insert(txn, request, None, row.barcode.clone()).await?;
+
+        // the starting balance has to show up in the ledger as well, it is no
+        // cash paid in, so neither the cashbox nor webhooks see it
+        if row.balance != 0 {
+            transaction::ActiveModel {
+                kind: Set(Kind::Deposit),
+                user: Set(Some(user.id)),
+                amount: Set(row.balance),
+                reference: Set(Some("import".to_owned())),
+                ..Default::default()
+            }
+            .insert(txn)
+            .await?;
+        }
+
+        let mut user: User = user.into();
+        user.barcode = row.barcode;
+        response.imported.push(user);
+    }
+    Ok(response)
+}
+
+/// Validates every row of the file and creates all users in a single
+/// transaction. If any row is invalid, nothing is imported and the errors of
+/// all rows are returned instead.
+pub(crate) async fn import(db: &Db, data: &str) -> Result<(StatusCode, UserImportResponse)> {
+    let (rows, mut errors) = parse_csv(data).map_err(AppError::InvalidInput)?;
+
+    db.orm
+        .transaction::<_, (StatusCode, UserImportResponse), AppError>(|txn| {
+            Box::pin(async move {
+                let names = user::Entity::find()
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|u| u.name)
+                    .collect();
+                let barcodes = barcode::Entity::find()
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|b| b.code)
+                    .collect();
+                errors.extend(check_conflicts(&rows, names, barcodes));
+
+                if errors.is_empty() {
+                    return Ok((StatusCode::CREATED, insert(txn, rows).await?));
+                }
+
+                errors.sort_by_key(|e| e.line);
+                let status = if errors.iter().any(|e| matches!(e.error, AppError::Conflict)) {
+                    StatusCode::CONFLICT
+                } else {
+                    StatusCode::BAD_REQUEST
+                };
+                Ok((
+                    status,
+                    UserImportResponse {
+                        imported: Vec::new(),
+                        errors: errors.iter().map(Into::into).collect(),
+                    },
+                ))
+            })
+        })
+        .await
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn messages(errors: &[RowError]) -> Vec<(usize, String)> {
+        errors
+            .iter()
+            .map(UserImportError::from)
+            .map(|e| (e.line, e.message))
+            .collect()
+    }
+
+    #[test]
+    fn parses_rows_and_reports_invalid_fields() {
+        let data = "\
+name,email,balance,barcode,audit
+alice,alice@example.org,500,4029764001807,yes
+bob,,,,
+,bob,1.50,,maybe
+";
+        let (rows, errors) = parse_csv(data).unwrap();
+
+        assert_eq!(
+            rows[..2],
+            [
+                ImportRow {
+                    line: 2,
+                    name: "alice".to_owned(),
+                    email: Some("alice@example.org".to_owned()),
+                    balance: 500,
+                    barcode: Some("4029764001807".to_owned()),
+                    audit: true,
+                },
+                ImportRow {
+                    line: 3,
+                    name: "bob".to_owned(),
+                    email: None,
+                    balance: 0,
+                    barcode: None,
+                    audit: false,
+                }
+            ]
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![
+                (4, "name: invalid input: must not be empty".to_owned()),
+                (
+                    4,
+                    "email: invalid input: \"bob\" is no email address".to_owned()
+                ),
+                (
+                    4,
+                    "balance: invalid input: \"1.50\": invalid digit found in string".to_owned()
+                ),
+                (
+                    4,
+                    "audit: invalid input: \"maybe\" is neither true nor false".to_owned()
+                ),
+            ]
+        );
+        assert!(parse_csv("email\nalice@example.org\n").is_err());
+    }
+
+    #[test]
+    fn duplicates_within_file_and_database_conflict() {
+        let (rows, _) = parse_csv("name,barcode\nalice,1\nbob,2\nbob,3\ncarol,1\n").unwrap();
+
+        let errors = check_conflicts(&rows, HashSet::from(["alice".to_owned()]), HashSet::new());
+
+        assert_eq!(
+            messages(&errors),
+            vec![
+                (2, "name: already exists".to_owned()),
+                (4, "name: already exists".to_owned()),
+                (5, "barcode: already exists".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn starting_balances_are_booked() {
+        for db in crate::testing::backends("import").await {
+            let data = "name,balance,barcode\nalice,500,4029764001807\nbob,,\ncarol,-200,\n";
+            let (status, response) = import(&db, data).await.unwrap();
+            assert_eq!(status, StatusCode::CREATED);
+
+            let ids = response.imported.iter().map(|u| u.id).collect::<Vec<_>>();
+            let deposits = transaction::Entity::find()
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.kind, t.user, t.amount, t.reference))
+                .collect::<Vec<_>>();
+            let import = Some("import".to_owned());
+            assert_eq!(
+                deposits,
+                vec![
+                    (Kind::Deposit, Some(ids[0]), 500, import.clone()),
+                    (Kind::Deposit, Some(ids[2]), -200, import),
+                ]
+            );
+
+            // a single webhook per user, carrying the barcode
+            let payloads = crate::entity::webhook_event::Entity::find()
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| serde_json::from_str::<User>(&e.payload).unwrap().barcode)
+                .collect::<Vec<_>>();
+            assert_eq!(payloads, vec![Some("4029764001807".to_owned()), None, None]);
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use axum::{http::StatusCode, response::IntoResponse, Json};
-use eyre::Report;
 use sea_orm::{DbErr, TransactionError};
 use serde_json::json;
 
@@ -9,47 +8,65 @@ pub(crate) type Result<T> = std::result::Result<T, AppError>;
 pub(crate) enum AppError {
     #[error("database error")]
     DbErr(#[from] DbErr),
-    #[error(transparent)]
-    Transaction(eyre::Report),
     #[error("already exists")]
     Conflict,
+    #[error("still referenced, e.g. by the ledger")]
+    Referenced,
     #[error("not found")]
     NotFount,
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("product is age restricted")]
+    AgeRestricted,
+    #[error("spending cap exceeded")]
+    CapExceeded,
     #[error(transparent)]
     ParseError(#[from] std::num::ParseIntError),
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
     #[error("{0:?}")]
     Error(#[from] eyre::Error),
 }
 
-impl<T> From<TransactionError<T>> for AppError
-where
-    T: std::error::Error + Send + Sync + 'static,
-{
-    fn from(err: TransactionError<T>) -> Self {
+impl From<TransactionError<AppError>> for AppError {
+    fn from(err: TransactionError<AppError>) -> Self {
         match err {
             TransactionError::Connection(err) => AppError::DbErr(err),
-            TransactionError::Transaction(err) => AppError::Transaction(Report::new(err)),
+            TransactionError::Transaction(err) => err,
         }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::http::Response<axum::body::BoxBody> {
-        // special case unique constraint failure and return 409
-        if let AppError::DbErr(DbErr::Exec(ref msg)) = self {
+        // special case unique and foreign key constraint failures and return
+        // 409, postgres reports failed inserts as query errors because of
+        // `RETURNING`
+        if let AppError::DbErr(DbErr::Exec(ref msg) | DbErr::Query(ref msg)) = self {
             // this is a ugly hack, but i'm not sure how to clean this up :(
-            if msg.contains("UNIQUE constraint failed") {
+            if msg.contains("UNIQUE constraint failed")
+                || msg.contains("duplicate key value violates unique constraint")
+            {
                 return AppError::Conflict.into_response();
             }
+            if msg.contains("FOREIGN KEY constraint failed")
+                || msg.contains("violates foreign key constraint")
+            {
+                return AppError::Referenced.into_response();
+            }
         };
 
         let message = format!("{:?}", self);
         let status = match self {
             AppError::DbErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            AppError::Transaction(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Conflict => StatusCode::CONFLICT,
+            AppError::Referenced => StatusCode::CONFLICT,
             AppError::NotFount => StatusCode::NOT_FOUND,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::AgeRestricted => StatusCode::FORBIDDEN,
+            AppError::CapExceeded => StatusCode::FORBIDDEN,
             AppError::ParseError(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
             AppError::Error(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -0,0 +1,481 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sea_orm::{
+    entity::*, query::*, sea_query::Query, ActiveEnum, ConnectionTrait, TransactionTrait,
+};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::{
+    config::{WebhookConfig, WebhookSubscription},
+    entity::{
+        webhook_delivery,
+        webhook_event::{self, Kind},
+    },
+    models::WebhookPayload,
+    storage::Db,
+    utils::{AppError, Result},
+};
+
+/// how often the worker looks for new events and failed deliveries
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// retries are delayed exponentially starting with this many seconds
+const RETRY_DELAY: i64 = 10;
+const MAX_RETRY_DELAY: i64 = 60 * 60;
+const DELIVERIES_PER_RUN: u64 = 100;
+/// how often old deliveries are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Queues an event for all webhooks subscribed to it. Should be called within
+/// the transaction of the change, so the event is only sent if it is committed.
+pub(crate) async fn emit(
+    db: &impl ConnectionTrait,
+    kind: Kind,
+    data: &impl Serialize,
+) -> Result<()> {
+    webhook_event::ActiveModel {
+        kind: Set(kind),
+        payload: Set(serde_json::to_string(data).map_err(eyre::Error::from)?),
+        dispatched: Set(false),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+fn subscribers(config: &WebhookConfig, kind: Kind) -> impl Iterator<Item = &WebhookSubscription> {
+    config
+        .subscriptions
+        .iter()
+        .filter(move |s| s.events.is_empty() || s.events.contains(&kind))
+}
+
+/// hex encoded HMAC-SHA256 of the body
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// delay before the next attempt after `attempts` failed ones
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    let factor = 2_i64.saturating_pow(attempts.saturating_sub(1).max(0) as u32);
+    chrono::Duration::seconds(RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY))
+}
+
+/// queues a delivery to every subscriber of new events
+async fn dispatch(db: &Db, config: &WebhookConfig) -> Result<()> {
+    let config = config.clone();
+    db.orm
+        .transaction::<_, (), AppError>(|txn| {
+            Box::pin(async move {
+                let events = webhook_event::Entity::find()
+                    .filter(webhook_event::Column::Dispatched.eq(false))
+                    .all(txn)
+                    .await?;
+                let now = Utc::now().naive_utc();
+                for event in events {
+                    let mut subscribed = false;
+                    for subscription in subscribers(&config, event.kind) {
+                        subscribed = true;
+                        webhook_delivery::ActiveModel {
+                            event: Set(event.id),
+                            url: Set(subscription.url.clone()),
+                            attempts: Set(0),
+                            next_attempt_at: Set(now),
+                            ..Default::default()
+                        }
+                        .insert(txn)
+                        .await?;
+                    }
+
+                    // nobody is interested, no need to keep it
+                    if !subscribed {
+                        event.into_active_model().delete(txn).await?;
+                        continue;
+                    }
+                    let mut event = event.into_active_model();
+                    event.dispatched = Set(true);
+                    event.update(txn).await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+}
+
+async fn post(
+    client: &reqwest::Client,
+    subscription: &WebhookSubscription,
+    delivery: i32,
+    event: webhook_event::Model,
+) -> eyre::Result<()> {
+    let payload = WebhookPayload {
+        id: event.id,
+        event: event.kind,
+        created_at: chrono::DateTime::from_utc(event.created_at, Utc),
+        data: serde_json::from_str(&event.payload)?,
+    };
+    let body = serde_json::to_vec(&payload)?;
+
+    let response = client
+        .post(&subscription.url)
+        .header("Content-Type", "application/json")
+        .header("X-Matekasse-Event", event.kind.to_value())
+        .header("X-Matekasse-Delivery", delivery.to_string())
+        .header(
+            "X-Matekasse-Signature",
+            format!("sha256={}", signature(&subscription.secret, &body)),
+        )
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        eyre::bail!("subscriber responded with {}", response.status());
+    }
+    Ok(())
+}
+
+/// posts every delivery that is due, failed ones are scheduled for a retry
+async fn deliver(db: &Db, config: &WebhookConfig, client: &reqwest::Client) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let deliveries = webhook_delivery::Entity::find()
+        .filter(webhook_delivery::Column::DeliveredAt.is_null())
+        .filter(webhook_delivery::Column::Attempts.lt(config.max_attempts))
+        .filter(webhook_delivery::Column::NextAttemptAt.lte(now))
+        .order_by_asc(webhook_delivery::Column::Id)
+        .limit(DELIVERIES_PER_RUN)
+        .all(&db.orm)
+        .await?;
+
+    for delivery in deliveries {
+        let event = match webhook_event::Entity::find_by_id(delivery.event)
+            .one(&db.orm)
+            .await?
+        {
+            Some(event) => event,
+            // can't ever succeed, the other deliveries are still sent
+            None => {
+                warn!(
+                    "webhook event {} of delivery {} is missing",
+                    delivery.event, delivery.id
+                );
+                let mut delivery = delivery.into_active_model();
+                delivery.attempts = Set(config.max_attempts);
+                delivery.last_error = Set(Some("event is missing".to_string()));
+                delivery.update(&db.orm).await?;
+                continue;
+            }
+        };
+        let attempts = delivery.attempts + 1;
+        let result = match config.subscriptions.iter().find(|s| s.url == delivery.url) {
+            Some(subscription) => post(client, subscription, delivery.id, event).await,
+            None => Err(eyre::eyre!("subscription was removed")),
+        };
+
+        let mut delivery = delivery.into_active_model();
+        delivery.attempts = Set(attempts);
+        match result {
+            Ok(()) => delivery.delivered_at = Set(Some(Utc::now().naive_utc())),
+            Err(err) => {
+                warn!("webhook delivery failed (attempt {}): {}", attempts, err);
+                delivery.last_error = Set(Some(err.to_string()));
+                delivery.next_attempt_at = Set(Utc::now().naive_utc() + retry_delay(attempts));
+            }
+        }
+        delivery.update(&db.orm).await?;
+    }
+    Ok(())
+}
+
+/// Deletes deliveries that succeeded or ran out of attempts before the
+/// retention period, and the events without any delivery left.
+async fn prune(db: &Db, config: &WebhookConfig) -> Result<()> {
+    let cutoff = Utc::now().naive_utc() - chrono::Duration::days(config.retention_days.into());
+    let max_attempts = config.max_attempts;
+    db.orm
+        .transaction::<_, (), AppError>(|txn| {
+            Box::pin(async move {
+                webhook_delivery::Entity::delete_many()
+                    .filter(
+                        Condition::any()
+                            .add(webhook_delivery::Column::DeliveredAt.lt(cutoff))
+                            .add(
+                                Condition::all()
+                                    .add(webhook_delivery::Column::DeliveredAt.is_null())
+                                    .add(webhook_delivery::Column::Attempts.gte(max_attempts))
+                                    .add(webhook_delivery::Column::NextAttemptAt.lt(cutoff)),
+                            ),
+                    )
+                    .exec(txn)
+                    .await?;
+                webhook_event::Entity::delete_many()
+                    .filter(webhook_event::Column::Dispatched.eq(true))
+                    .filter(
+                        webhook_event::Column::Id.not_in_subquery(
+                            Query::select()
+                                .column(webhook_delivery::Column::Event)
+                                .from(webhook_delivery::Entity)
+                                .to_owned(),
+                        ),
+                    )
+                    .exec(txn)
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+}
+
+/// Background task posting queued events to the subscribed webhooks. Events
+/// are kept in the database, so deliveries that were still pending or failed
+/// are picked up again after a restart.
+pub async fn worker(db: Db, config: WebhookConfig) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("unable to set up webhook client: {:?}", err);
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut prune_interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(err) = dispatch(&db, &config).await {
+                    warn!("unable to dispatch webhook events: {:?}", err);
+                }
+                if let Err(err) = deliver(&db, &config, &client).await {
+                    warn!("unable to deliver webhooks: {:?}", err);
+                }
+            }
+            _ = prune_interval.tick() => {
+                if let Err(err) = prune(&db, &config).await {
+                    warn!("unable to delete old webhook deliveries: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{storage::Pool, testing};
+
+    #[test]
+    fn payloads_are_signed_with_the_secret() {
+        // test case 2 of RFC 4231
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn subscriptions_and_retries() {
+        let subscription = |url: &str, events| WebhookSubscription {
+            url: url.to_string(),
+            secret: "secret".to_string(),
+            events,
+        };
+        let config = WebhookConfig {
+            subscriptions: vec![
+                subscription("http://led", vec![Kind::Purchase]),
+                subscription("http://chat", vec![]),
+            ],
+            ..Default::default()
+        };
+        let urls = |kind| {
+            subscribers(&config, kind)
+                .map(|s| s.url.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(urls(Kind::Purchase), vec!["http://led", "http://chat"]);
+        assert_eq!(urls(Kind::Deposit), vec!["http://chat"]);
+        assert_eq!(
+            [1, 2, 3, 20].map(|a| retry_delay(a).num_seconds()),
+            [10, 20, 40, MAX_RETRY_DELAY]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_later() {
+        let config = WebhookConfig {
+            // nothing listens on the discard port
+            subscriptions: vec![WebhookSubscription {
+                url: "http://127.0.0.1:9/".to_string(),
+                secret: "secret".to_string(),
+                events: vec![Kind::Deposit],
+            }],
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        for db in testing::backends("webhooks").await {
+            emit(&db.orm, Kind::Deposit, &42).await.unwrap();
+            emit(&db.orm, Kind::Purchase, &43).await.unwrap();
+            dispatch(&db, &config).await.unwrap();
+            // events nobody subscribed to are dropped
+            let events = webhook_event::Entity::find().all(&db.orm).await.unwrap();
+            assert_eq!(
+                events
+                    .iter()
+                    .map(|e| (e.kind, e.dispatched))
+                    .collect::<Vec<_>>(),
+                vec![(Kind::Deposit, true)]
+            );
+
+            deliver(&db, &config, &client).await.unwrap();
+            let deliveries = webhook_delivery::Entity::find().all(&db.orm).await.unwrap();
+            assert_eq!(deliveries.len(), 1);
+            assert_eq!(deliveries[0].attempts, 1);
+            assert!(deliveries[0].delivered_at.is_none() && deliveries[0].last_error.is_some());
+            assert!(deliveries[0].next_attempt_at > Utc::now().naive_utc());
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_events_do_not_stop_other_deliveries() {
+        let url = "http://127.0.0.1:9/";
+        let config = WebhookConfig {
+            subscriptions: vec![WebhookSubscription {
+                url: url.to_string(),
+                secret: "secret".to_string(),
+                events: vec![Kind::Deposit],
+            }],
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        for db in testing::backends("webhooks_missing").await {
+            // the foreign key keeps events from going missing in the first place
+            let orphan = "INSERT INTO webhook_delivery (event, url) VALUES (1000, $1)";
+            match &db.pool {
+                #[cfg(feature = "sqlite")]
+                Pool::Sqlite(pool) => {
+                    let mut conn = pool.acquire().await.unwrap();
+                    sqlx::query("PRAGMA foreign_keys = OFF")
+                        .execute(&mut conn)
+                        .await
+                        .unwrap();
+                    sqlx::query(orphan)
+                        .bind(url)
+                        .execute(&mut conn)
+                        .await
+                        .unwrap();
+                }
+                #[cfg(feature = "postgres")]
+                Pool::Postgres(pool) => {
+                    sqlx::query(
+                        "ALTER TABLE webhook_delivery DROP CONSTRAINT webhook_delivery_event_fkey",
+                    )
+                    .execute(pool)
+                    .await
+                    .unwrap();
+                    sqlx::query(orphan).bind(url).execute(pool).await.unwrap();
+                }
+            }
+            emit(&db.orm, Kind::Deposit, &42).await.unwrap();
+            dispatch(&db, &config).await.unwrap();
+
+            deliver(&db, &config, &client).await.unwrap();
+            let deliveries = webhook_delivery::Entity::find()
+                .order_by_asc(webhook_delivery::Column::Id)
+                .all(&db.orm)
+                .await
+                .unwrap();
+            assert_eq!(
+                deliveries
+                    .iter()
+                    .map(|d| (d.attempts, d.last_error.is_some()))
+                    .collect::<Vec<_>>(),
+                vec![(config.max_attempts, true), (1, true)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn old_deliveries_and_their_events_are_deleted() {
+        let config = WebhookConfig::default();
+        // whole seconds, postgres only keeps microseconds
+        let now = Utc::now().naive_utc().date().and_hms(12, 0, 0);
+        let long_ago = now - chrono::Duration::days(100);
+        for db in testing::backends("webhooks_prune").await {
+            let mut events = Vec::new();
+            for dispatched in [true, true, true, true, false] {
+                let event = webhook_event::ActiveModel {
+                    kind: Set(Kind::Deposit),
+                    payload: Set("42".to_string()),
+                    dispatched: Set(dispatched),
+                    created_at: Set(long_ago),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+                events.push(event.id);
+            }
+            let deliveries = [
+                // delivered long ago
+                (events[0], 1, Some(long_ago)),
+                // delivered recently
+                (events[1], 1, Some(now)),
+                // given up long ago
+                (events[2], config.max_attempts, None),
+                // still retried, the delivered one of the same event goes
+                (events[3], 1, Some(long_ago)),
+                (events[3], 3, None),
+            ];
+            for (event, attempts, delivered_at) in deliveries {
+                webhook_delivery::ActiveModel {
+                    event: Set(event),
+                    url: Set("http://chat".to_string()),
+                    attempts: Set(attempts),
+                    next_attempt_at: Set(long_ago),
+                    delivered_at: Set(delivered_at),
+                    ..Default::default()
+                }
+                .insert(&db.orm)
+                .await
+                .unwrap();
+            }
+
+            prune(&db, &config).await.unwrap();
+
+            let remaining = webhook_delivery::Entity::find()
+                .order_by_asc(webhook_delivery::Column::Id)
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.event, d.attempts))
+                .collect::<Vec<_>>();
+            assert_eq!(remaining, vec![(events[1], 1), (events[3], 3)]);
+            let remaining = webhook_event::Entity::find()
+                .order_by_asc(webhook_event::Column::Id)
+                .all(&db.orm)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id)
+                .collect::<Vec<_>>();
+            // events that are not dispatched yet have no deliveries either
+            assert_eq!(remaining, vec![events[1], events[3], events[4]]);
+        }
+    }
+}